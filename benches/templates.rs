@@ -0,0 +1,107 @@
+//! Compares compiling the Handlebars templates for every page against
+//! compiling them once and sharing them, then times a full build of a large
+//! generated site. Run with `cargo bench`, setting `MADE_UP_BENCH_PAGES` to
+//! change the size of the site.
+extern crate handlebars;
+extern crate made_up;
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use handlebars::Handlebars;
+use serde_json::{Map, Value as Json};
+
+const CONTAINER: &str = include_str!("../templates/container.hbs");
+const PAGE: &str = include_str!("../templates/basic.hbs");
+const SECTION: &str = include_str!("../templates/section.hbs");
+
+fn compile() -> Handlebars {
+    let mut handlebars = Handlebars::new();
+    handlebars
+        .register_template_string("container", CONTAINER)
+        .unwrap();
+    handlebars.register_partial("content", PAGE).unwrap();
+    handlebars.register_partial("section", SECTION).unwrap();
+    handlebars
+}
+
+fn page_data(index: usize) -> Map<String, Json> {
+    let mut data = Map::new();
+    data.insert("title".to_string(), Json::String(format!("Site - Page {}", index)));
+    data.insert("site_root".to_string(), Json::String(String::new()));
+    data.insert("stylesheet".to_string(), Json::Array(vec![]));
+    let content = format!("<h1 id=\"page-{0}\">Page {0}</h1><p>Content</p>", index);
+    data.insert("md_content".to_string(), Json::String(content));
+    data
+}
+
+/// Write a site of `pages` Markdown files spread over a few directories
+fn generate_site(root: &Path, pages: usize) {
+    let _ = fs::remove_dir_all(root);
+    fs::create_dir_all(root.join("images")).unwrap();
+    fs::write(root.join("mdup.yml"), "title: Benchmark\n").unwrap();
+    for index in 0..pages {
+        let dir = root.join(format!("section-{}", index % 10));
+        fs::create_dir_all(&dir).unwrap();
+        let content = format!(
+            "# Page {0}\n\n[TOC]\n\n## Usage\n\nSome *text* with a [link](page-{1}.md).\n\n\
+             ```rust\nfn main() {{}}\n```\n\n## Details\n\n| a | b |\n|---|---|\n| 1 | 2 |\n",
+            index,
+            index + 10
+        );
+        fs::write(dir.join(format!("page-{}.md", index)), content).unwrap();
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + f64::from(duration.subsec_nanos()) / 1_000_000.0
+}
+
+fn main() {
+    let pages = env::var("MADE_UP_BENCH_PAGES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(1000);
+
+    let start = Instant::now();
+    for index in 0..pages {
+        compile().render("container", &page_data(index)).unwrap();
+    }
+    let per_page = start.elapsed();
+
+    let start = Instant::now();
+    let handlebars = compile();
+    for index in 0..pages {
+        handlebars.render("container", &page_data(index)).unwrap();
+    }
+    let shared = start.elapsed();
+
+    println!("Rendering {} pages", pages);
+    println!("  compiled for every page: {:>10.2} ms", millis(per_page));
+    println!("  compiled once:           {:>10.2} ms", millis(shared));
+    println!(
+        "  speedup:                 {:>10.2}x",
+        millis(per_page) / millis(shared)
+    );
+
+    let root = env::temp_dir().join("made-up-bench");
+    generate_site(&root, pages);
+    let mut convertor = made_up::Convertor::new(&root).unwrap();
+    convertor.set_out_dir(root.join("out"));
+    convertor.set_force(true);
+    for &jobs in &[1, 4] {
+        convertor.set_jobs(jobs);
+        let start = Instant::now();
+        let files = convertor.generate_site().unwrap();
+        println!(
+            "Generating a site of {} pages on {} thread(s): {:.2} ms",
+            files.len() - 1,
+            jobs,
+            millis(start.elapsed())
+        );
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -3,7 +3,12 @@ extern crate clap;
 extern crate log;
 extern crate made_up;
 
-use clap::{App, Arg};
+use std::env;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
 use made_up::{Error, ErrorKind};
 
 fn main() {
@@ -19,7 +24,66 @@ fn main() {
         .arg(
             Arg::with_name("debug")
                 .help("Debug level logs")
-                .long("debug"),
+                .long("debug")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("force")
+                .help("Render every page, even those unchanged since the last build")
+                .long("force")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("drafts")
+                .help("Include the pages marked as drafts")
+                .long("drafts")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("future")
+                .help("Include the pages dated in the future or which have expired")
+                .long("future")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .help("Number of threads to render pages on, defaults to the number of CPUs")
+                .long("jobs")
+                .short("j")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .help("Rebuild the site as its files change")
+                .long("watch")
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Build the site and serve it over HTTP on localhost")
+                .arg(
+                    Arg::with_name("root_dir")
+                        .help("Root directory of Markdown files")
+                        .index(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("port")
+                        .help("Port to serve the site on")
+                        .long("port")
+                        .short("p")
+                        .takes_value(true)
+                        .default_value("8000"),
+                )
+                .arg(
+                    Arg::with_name("temp")
+                        .help(
+                            "Build into a temporary directory instead of the configured output, \
+                             which is left in place when the server stops",
+                        )
+                        .long("temp"),
+                ),
         )
         .get_matches();
 
@@ -34,8 +98,76 @@ fn main() {
         Box::new(SimpleLogger::new(log_level))
     }).unwrap();
 
-    let dir = matches.value_of("root_dir").unwrap_or(".");
-    let convertor: made_up::Convertor = handle_error(made_up::Convertor::new(dir));
+    if let Some(serve_matches) = matches.subcommand_matches("serve") {
+        let dir = serve_matches.value_of("root_dir").unwrap_or(".");
+        let mut convertor: made_up::Convertor = handle_error(made_up::Convertor::new(dir));
+        if serve_matches.is_present("temp") {
+            convertor.set_out_dir(env::temp_dir().join(format!("made-up-{}", process::id())));
+        }
+        convertor.set_force(serve_matches.is_present("force"));
+        set_publishing(&mut convertor, serve_matches);
+        set_jobs(&mut convertor, serve_matches);
+        let watch = serve_matches.is_present("watch");
+        convertor.set_live_reload(watch);
+        // When watching, the first build is made by the watch so that its
+        // errors are reported in the same way as those of the rebuilds
+        if !watch {
+            build(&convertor);
+        }
+        let port = value_t!(serve_matches, "port", u16).unwrap_or_else(|e| e.exit());
+        let mut server = handle_error(made_up::Server::bind(
+            convertor.out_dir(),
+            &format!("127.0.0.1:{}", port),
+        ));
+        if watch {
+            let live_reload = Arc::new(made_up::LiveReload::new());
+            server.set_live_reload(live_reload.clone());
+            thread::spawn(move || handle_error(server.run()));
+            convertor.watch(|result| match result {
+                Ok(()) => live_reload.reload(),
+                Err(e) => {
+                    let message = describe_error(&e);
+                    println!("{}", message);
+                    live_reload.build_failed(message);
+                }
+            });
+        } else {
+            handle_error(server.run());
+        }
+    } else {
+        let dir = matches.value_of("root_dir").unwrap_or(".");
+        let mut convertor: made_up::Convertor = handle_error(made_up::Convertor::new(dir));
+        convertor.set_force(matches.is_present("force"));
+        set_publishing(&mut convertor, &matches);
+        set_jobs(&mut convertor, &matches);
+        if matches.is_present("watch") {
+            convertor.watch(|result| {
+                if let Err(e) = result {
+                    println!("{}", describe_error(&e));
+                }
+            });
+        } else {
+            build(&convertor);
+        }
+    }
+}
+
+/// Include the drafts and the pages outside of their dates as asked for
+fn set_publishing(convertor: &mut made_up::Convertor, matches: &ArgMatches) {
+    convertor.set_drafts(matches.is_present("drafts"));
+    convertor.set_future(matches.is_present("future"));
+}
+
+/// Use the number of threads given by `--jobs`, if any
+fn set_jobs(convertor: &mut made_up::Convertor, matches: &ArgMatches) {
+    if matches.is_present("jobs") {
+        let jobs = value_t!(matches, "jobs", usize).unwrap_or_else(|e| e.exit());
+        convertor.set_jobs(jobs);
+    }
+}
+
+/// Generate the site and write it to the output directory
+fn build(convertor: &made_up::Convertor) {
     let files = handle_error(convertor.generate_site());
     handle_error(convertor.write_files(files));
 }
@@ -44,24 +176,29 @@ use std::fmt::Debug;
 fn handle_error<T: Debug>(possible_error: Result<T, Error>) -> T {
     match possible_error {
         Err(err) => {
-            match err {
-                made_up::Error(ErrorKind::Config(e), _) => println!("Configuration Error: {:?}", e),
-                made_up::Error(ErrorKind::Fail(e), _) => println!("Error: {}", e),
-                made_up::Error(ErrorKind::IO(e), _) => println!("IO Error: {:?}", e),
-                made_up::Error(ErrorKind::Template(e), _) => {
-                    println!("Template Generation Error: {:?}", e);
-                }
-                made_up::Error(ErrorKind::TemplateCompile(e), _) => {
-                    println!("Template Compilation Error: {:?}", e);
-                }
-                made_up::Error(ErrorKind::Msg(msg), _) => println!("{}", msg),
-            }
+            println!("{}", describe_error(&err));
             std::process::exit(1);
         }
         Ok(res) => res,
     }
 }
 
+/// Message describing the error to the user
+fn describe_error(err: &Error) -> String {
+    match *err {
+        made_up::Error(ErrorKind::Config(ref e), _) => format!("Configuration Error: {:?}", e),
+        made_up::Error(ErrorKind::Fail(ref e), _) => format!("Error: {}", e),
+        made_up::Error(ErrorKind::IO(ref e), _) => format!("IO Error: {:?}", e),
+        made_up::Error(ErrorKind::Template(ref e), _) => {
+            format!("Template Generation Error: {:?}", e)
+        }
+        made_up::Error(ErrorKind::TemplateCompile(ref e), _) => {
+            format!("Template Compilation Error: {:?}", e)
+        }
+        made_up::Error(ErrorKind::Msg(ref msg), _) => msg.to_string(),
+    }
+}
+
 use log::{LogLevel, LogMetadata, LogRecord};
 
 struct SimpleLogger {
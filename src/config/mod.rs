@@ -12,9 +12,28 @@ mod config_macro;
 configuration!{
     stylesheet, Vec<String>, vec![];
     index_template, Option<String>, None;
+    templates_dir, Option<String>, None;
+    theme, String, "light".to_string();
     out_dir, String, "out".to_string();
     copy_resources, bool, true;
-    title, String, "Title".to_string()
+    use_cdn, bool, false;
+    highlight, String, "client".to_string();
+    highlight_scheme, String, "tomorrow-night".to_string();
+    title, String, "Title".to_string();
+    heading_anchors, bool, false;
+    toc_min_depth, i32, 2;
+    toc_max_depth, i32, 3;
+    show_backlinks, bool, true;
+    taxonomies, Vec<String>, vec!["tags".to_string()];
+    sort_by, String, "name".to_string();
+    order, Vec<String>, vec![]
+}
+
+impl Configuration {
+    /// Override the directory the site will be written to
+    pub fn set_out_dir(&mut self, out_dir: String) {
+        self.out_dir = out_dir;
+    }
 }
 
 #[cfg(test)]
@@ -28,6 +47,8 @@ mod tests {
         assert_eq!(actual.out_dir, None);
         assert_eq!(actual.copy_resources, None);
         assert_eq!(actual.title, Some("My Site".to_string()));
+        assert_eq!(actual.heading_anchors, None);
+        assert_eq!(actual.templates_dir, None);
     }
 
     #[test]
@@ -58,6 +79,7 @@ mod tests {
         assert_eq!(actual.out_dir, "out".to_string());
         assert_eq!(actual.copy_resources, true);
         assert_eq!(actual.title, "My Site".to_string());
+        assert_eq!(actual.heading_anchors, false);
     }
 
     #[test]
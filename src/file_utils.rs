@@ -1,6 +1,6 @@
 use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use Result;
 
 /// Writes the provided content to a file at the path provided.
@@ -42,6 +42,26 @@ pub fn copy_file<P: AsRef<Path>, Q: AsRef<Path>>(
     Ok(())
 }
 
+/// Removes the `.` components of the path and the directories which `..`
+/// components leave, without touching the file system. Allows paths reached
+/// in different ways to be compared, i.e. `docs/../page.md` and `./page.md`.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                _ => normalized.push(".."),
+            },
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
@@ -75,4 +95,17 @@ mod tests {
     fn test_check_dir_exists() {
         assert!(super::check_dir_exists("src"));
     }
+
+    #[test]
+    fn test_normalize_path() {
+        use std::path::{Path, PathBuf};
+        assert_eq!(
+            super::normalize_path(Path::new("./docs/guide/../_shared/./note.md")),
+            PathBuf::from("docs/_shared/note.md")
+        );
+        assert_eq!(
+            super::normalize_path(Path::new("../src/../../lib.rs")),
+            PathBuf::from("../../lib.rs")
+        );
+    }
 }
@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_yaml::{self, Value as Yaml};
+
+/// Taxonomy whose terms are given by the `tags` of the front matter.
+const TAGS: &str = "tags";
+/// Line used to open and close the YAML front matter block.
+const DELIMITER: &str = "---";
+/// Alternative YAML document end marker which may also close the block.
+const END_DELIMITER: &str = "...";
+/// Length of the date within the dates of the front matter, `YYYY-MM-DD`.
+const DATE_LENGTH: usize = 10;
+
+/// Metadata defined for a page within the optional YAML block at the top of
+/// the Markdown file. Any keys which are not known are kept in `extra` so they
+/// can still be used within the templates.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct FrontMatter {
+    title: Option<String>,
+    description: Option<String>,
+    date: Option<String>,
+    expires: Option<String>,
+    tags: Vec<String>,
+    draft: bool,
+    weight: Option<i64>,
+    template: Option<String>,
+    slug: Option<String>,
+    #[serde(flatten)]
+    extra: BTreeMap<String, Yaml>,
+}
+
+impl FrontMatter {
+    /// Returns the title of the page if one was given
+    pub fn title(&self) -> Option<&String> {
+        self.title.as_ref()
+    }
+
+    /// Returns the name of the template to render the page with, if one was
+    /// given
+    pub fn template(&self) -> Option<&String> {
+        self.template.as_ref()
+    }
+
+    /// Returns the date of the page if one was given
+    pub fn date(&self) -> Option<&String> {
+        self.date.as_ref()
+    }
+
+    /// Returns the weight of the page if one was given, lighter pages being
+    /// listed first when sorting by weight
+    pub fn weight(&self) -> Option<i64> {
+        self.weight
+    }
+
+    /// Whether the page is a draft, which is not published
+    pub fn is_draft(&self) -> bool {
+        self.draft
+    }
+
+    /// Whether the `date` of the page is after `today`, so it is not yet
+    /// published. Returns an error if the date is not valid.
+    pub fn is_scheduled(&self, today: &str) -> Result<bool, String> {
+        match self.date {
+            Some(ref date) => Ok(parse_date(date)? > today),
+            None => Ok(false),
+        }
+    }
+
+    /// Whether the page `expires` on or before `today`, so it is no longer
+    /// published. Returns an error if the date is not valid.
+    pub fn is_expired(&self, today: &str) -> Result<bool, String> {
+        match self.expires {
+            Some(ref expires) => Ok(parse_date(expires)? <= today),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the terms of the page for the taxonomy, i.e. its `tags`. Other
+    /// taxonomies are read from the extra keys, where a single term may be
+    /// given without a list.
+    pub fn terms(&self, taxonomy: &str) -> Vec<String> {
+        if taxonomy == TAGS {
+            return self.tags.clone();
+        }
+        match self.extra.get(taxonomy) {
+            Some(Yaml::String(term)) => vec![term.clone()],
+            Some(Yaml::Sequence(terms)) => terms
+                .iter()
+                .filter_map(|x| x.as_str())
+                .map(str::to_owned)
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Splits the provided Markdown content into its front matter and the
+/// remaining Markdown. When the content does not begin with a front matter
+/// block the default `FrontMatter` is returned along with all of the content.
+/// Returns an error if the block is not valid YAML.
+pub fn split(content: &str) -> Result<(FrontMatter, &str), serde_yaml::Error> {
+    match find_block(content) {
+        Some((yaml, body)) => {
+            if yaml.trim().is_empty() {
+                return Ok((FrontMatter::default(), body));
+            }
+            Ok((serde_yaml::from_str(yaml)?, body))
+        }
+        None => Ok((FrontMatter::default(), content)),
+    }
+}
+
+/// Returns the current date in UTC as `YYYY-MM-DD`, to compare against the
+/// dates of the front matter.
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() / 86_400)
+        .unwrap_or(0);
+    date_from_days(days as i64)
+}
+
+/// Date which is the number of days after 1970-01-01, using the algorithm
+/// for civil dates by Howard Hinnant.
+fn date_from_days(days: i64) -> String {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months counted from March so that the leap day is last
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Returns the `YYYY-MM-DD` date of a date within the front matter, which
+/// may be followed by a time, or why it is not a date.
+fn parse_date(date: &str) -> Result<&str, String> {
+    let date = date.trim();
+    let invalid = || format!("{:?} is not a date as YYYY-MM-DD", date);
+    let day = date.get(..DATE_LENGTH).ok_or_else(invalid)?;
+    let time = &date[DATE_LENGTH..];
+    let parts: Vec<&str> = day.split('-').collect();
+    let number = |x: &str, length: usize| -> Option<u32> {
+        if x.len() == length && x.chars().all(|c| c.is_ascii_digit()) {
+            x.parse().ok()
+        } else {
+            None
+        }
+    };
+    let valid = parts.len() == 3
+        && number(parts[0], 4).is_some()
+        && number(parts[1], 2).is_some_and(|x| (1..=12).contains(&x))
+        && number(parts[2], 2).is_some_and(|x| (1..=31).contains(&x))
+        && (time.is_empty() || time.starts_with('T') || time.starts_with(' '));
+    if valid {
+        Ok(day)
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Locate the front matter block, returning the YAML within the delimiters and
+/// the content following the closing delimiter.
+fn find_block(content: &str) -> Option<(&str, &str)> {
+    // Allow for files saved with a byte order mark
+    let content = content.trim_start_matches('\u{feff}');
+    let first_end = content.find('\n')?;
+    if content[..first_end].trim_end() != DELIMITER {
+        return None;
+    }
+    let mut offset = first_end + 1;
+    while offset < content.len() {
+        let end = content[offset..]
+            .find('\n')
+            .map(|x| offset + x)
+            .unwrap_or_else(|| content.len());
+        let line = content[offset..end].trim_end();
+        if line == DELIMITER || line == END_DELIMITER {
+            let body = if end < content.len() {
+                &content[end + 1..]
+            } else {
+                ""
+            };
+            return Some((&content[first_end + 1..offset], body));
+        }
+        offset = end + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::Value as Yaml;
+
+    #[test]
+    fn test_split_front_matter() {
+        let content = "---\ntitle: My Page\ntags:\n  - rust\n  - notes\ndraft: true\n\
+                       weight: 3\nauthor: Someone\n---\n# Heading\nBody\n";
+        let (front_matter, body) = super::split(content).unwrap();
+        assert_eq!(body, "# Heading\nBody\n");
+        assert_eq!(front_matter.title(), Some(&"My Page".to_string()));
+        assert_eq!(front_matter.tags, vec!["rust".to_string(), "notes".to_string()]);
+        assert!(front_matter.draft);
+        assert_eq!(front_matter.weight, Some(3));
+        assert_eq!(front_matter.description, None);
+        assert_eq!(
+            front_matter.extra.get("author"),
+            Some(&Yaml::String("Someone".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_terms() {
+        let content = "---\ntags: [rust]\nauthors: Someone\ncategories:\n  - notes\n---\n";
+        let (front_matter, _) = super::split(content).unwrap();
+        assert_eq!(front_matter.terms("tags"), vec!["rust".to_string()]);
+        assert_eq!(front_matter.terms("authors"), vec!["Someone".to_string()]);
+        assert_eq!(front_matter.terms("categories"), vec!["notes".to_string()]);
+        assert!(front_matter.terms("series").is_empty());
+    }
+
+    #[test]
+    fn test_publication_dates() {
+        let content = "---\ndate: 2024-03-01\nexpires: 2024-06-01T09:00:00Z\n---\n";
+        let (front_matter, _) = super::split(content).unwrap();
+        assert!(!front_matter.is_draft());
+        assert_eq!(front_matter.is_scheduled("2024-02-29"), Ok(true));
+        assert_eq!(front_matter.is_scheduled("2024-03-01"), Ok(false));
+        assert_eq!(front_matter.is_expired("2024-05-31"), Ok(false));
+        assert_eq!(front_matter.is_expired("2024-06-01"), Ok(true));
+
+        let (front_matter, _) = super::split("---\ndate: 1st March\n---\n").unwrap();
+        assert!(front_matter.is_scheduled("2024-03-01").is_err());
+        assert!(super::parse_date("2024-13-01").is_err());
+        assert!(super::parse_date("2024-1-01").is_err());
+        assert_eq!(super::parse_date("2024-01-05 10:00"), Ok("2024-01-05"));
+    }
+
+    #[test]
+    fn test_date_from_days() {
+        assert_eq!(super::date_from_days(0), "1970-01-01");
+        assert_eq!(super::date_from_days(19_782), "2024-02-29");
+        assert_eq!(super::date_from_days(-1), "1969-12-31");
+        assert_eq!(super::today().len(), super::DATE_LENGTH);
+    }
+
+    #[test]
+    fn test_no_front_matter() {
+        let content = "# Heading\n---\nnot: front matter\n---\n";
+        let (front_matter, body) = super::split(content).unwrap();
+        assert_eq!(front_matter, super::FrontMatter::default());
+        assert_eq!(body, content);
+    }
+
+    #[test]
+    fn test_unclosed_front_matter() {
+        let content = "---\n# Heading\n";
+        let (front_matter, body) = super::split(content).unwrap();
+        assert_eq!(front_matter, super::FrontMatter::default());
+        assert_eq!(body, content);
+    }
+
+    #[test]
+    fn test_empty_front_matter() {
+        let (front_matter, body) = super::split("---\r\n---\r\n# Heading").unwrap();
+        assert_eq!(front_matter, super::FrontMatter::default());
+        assert_eq!(body, "# Heading");
+    }
+
+    #[test]
+    fn test_invalid_front_matter() {
+        assert!(super::split("---\ntitle: [unclosed\n---\n# Heading").is_err());
+    }
+}
@@ -0,0 +1,656 @@
+use config::Configuration;
+use html::escape_html;
+use {ErrorKind, Result};
+
+/// Name of the stylesheet written to the site when the code blocks are
+/// highlighted with classes.
+pub const STYLESHEET_NAME: &str = "syntax.css";
+
+/// How the code blocks of the pages are highlighted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Highlight {
+    /// Left to highlight.js within the browser
+    Client,
+    /// At build time into spans classed by the kind of token, styled by the
+    /// stylesheet of the colour scheme
+    Classes(&'static Scheme),
+    /// At build time into spans coloured by inline styles
+    Inline(&'static Scheme),
+}
+
+/// Kind of token within a code block which is highlighted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Comment,
+    Keyword,
+    Literal,
+    Meta,
+    Number,
+    String,
+    Type,
+}
+
+impl Token {
+    /// Class of the span holding the token, the same as highlight.js uses
+    fn class(&self) -> &'static str {
+        match *self {
+            Token::Comment => "hljs-comment",
+            Token::Keyword => "hljs-keyword",
+            Token::Literal => "hljs-literal",
+            Token::Meta => "hljs-meta",
+            Token::Number => "hljs-number",
+            Token::String => "hljs-string",
+            Token::Type => "hljs-type",
+        }
+    }
+}
+
+/// Colours used to highlight code.
+#[derive(Debug, PartialEq)]
+pub struct Scheme {
+    name: &'static str,
+    background: &'static str,
+    foreground: &'static str,
+    comment: &'static str,
+    keyword: &'static str,
+    literal: &'static str,
+    meta: &'static str,
+    number: &'static str,
+    string: &'static str,
+    type_name: &'static str,
+}
+
+impl Scheme {
+    fn colour(&self, token: Token) -> &'static str {
+        match token {
+            Token::Comment => self.comment,
+            Token::Keyword => self.keyword,
+            Token::Literal => self.literal,
+            Token::Meta => self.meta,
+            Token::Number => self.number,
+            Token::String => self.string,
+            Token::Type => self.type_name,
+        }
+    }
+}
+
+const SCHEMES: &[Scheme] = &[
+    Scheme {
+        name: "tomorrow-night",
+        background: "#1d1f21",
+        foreground: "#c5c8c6",
+        comment: "#969896",
+        keyword: "#b294bb",
+        literal: "#de935f",
+        meta: "#8abeb7",
+        number: "#de935f",
+        string: "#b5bd68",
+        type_name: "#f0c674",
+    },
+    Scheme {
+        name: "tomorrow",
+        background: "#ffffff",
+        foreground: "#4d4d4c",
+        comment: "#8e908c",
+        keyword: "#8959a8",
+        literal: "#f5871f",
+        meta: "#3e999f",
+        number: "#f5871f",
+        string: "#718c00",
+        type_name: "#c99e00",
+    },
+    Scheme {
+        name: "solarized-light",
+        background: "#fdf6e3",
+        foreground: "#657b83",
+        comment: "#93a1a1",
+        keyword: "#859900",
+        literal: "#b58900",
+        meta: "#cb4b16",
+        number: "#d33682",
+        string: "#2aa198",
+        type_name: "#268bd2",
+    },
+];
+
+/// Rules to split the code of a language into tokens.
+#[derive(Debug)]
+struct Language {
+    names: &'static [&'static str],
+    keywords: &'static [&'static str],
+    literals: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    // Prefixes of the lines which are attributes or preprocessor directives
+    meta: &'static [&'static str],
+    // Whether identifiers starting with a capital letter name types
+    capital_types: bool,
+    // Whether `'` starts a lifetime unless it is a character literal
+    lifetimes: bool,
+}
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        names: &["rust", "rs"],
+        keywords: &[
+            "as", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "fn",
+            "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+            "return", "self", "Self", "static", "struct", "super", "trait", "type", "unsafe",
+            "use", "where", "while",
+        ],
+        literals: &["true", "false"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        meta: &["#[", "#!["],
+        capital_types: true,
+        lifetimes: true,
+    },
+    Language {
+        names: &["c", "h", "cpp", "c++", "cc", "hpp"],
+        keywords: &[
+            "auto", "break", "case", "char", "class", "const", "continue", "default", "delete",
+            "do", "double", "else", "enum", "extern", "float", "for", "goto", "if", "inline",
+            "int", "long", "namespace", "new", "private", "protected", "public", "register",
+            "return", "short", "signed", "sizeof", "static", "struct", "switch", "template",
+            "this", "typedef", "union", "unsigned", "using", "virtual", "void", "volatile",
+            "while",
+        ],
+        literals: &["true", "false", "NULL", "nullptr"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        meta: &["#"],
+        capital_types: false,
+        lifetimes: false,
+    },
+    Language {
+        names: &["java", "kotlin", "scala"],
+        keywords: &[
+            "abstract", "boolean", "break", "byte", "case", "catch", "char", "class", "continue",
+            "default", "do", "double", "else", "enum", "extends", "final", "finally", "float",
+            "for", "fun", "if", "implements", "import", "instanceof", "int", "interface", "long",
+            "new", "object", "package", "private", "protected", "public", "return", "short",
+            "static", "super", "switch", "this", "throw", "throws", "try", "val", "var", "void",
+            "while",
+        ],
+        literals: &["true", "false", "null"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        meta: &["@"],
+        capital_types: true,
+        lifetimes: false,
+    },
+    Language {
+        names: &["javascript", "js", "typescript", "ts"],
+        keywords: &[
+            "async", "await", "break", "case", "catch", "class", "const", "continue", "default",
+            "delete", "do", "else", "export", "extends", "finally", "for", "from", "function",
+            "if", "import", "in", "instanceof", "interface", "let", "new", "of", "return",
+            "static", "super", "switch", "this", "throw", "try", "typeof", "var", "void",
+            "while", "yield",
+        ],
+        literals: &["true", "false", "null", "undefined", "NaN"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        meta: &[],
+        capital_types: true,
+        lifetimes: false,
+    },
+    Language {
+        names: &["go"],
+        keywords: &[
+            "break", "case", "chan", "const", "continue", "default", "defer", "else",
+            "fallthrough", "for", "func", "go", "goto", "if", "import", "interface", "map",
+            "package", "range", "return", "select", "struct", "switch", "type", "var",
+        ],
+        literals: &["true", "false", "nil", "iota"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        meta: &[],
+        capital_types: false,
+        lifetimes: false,
+    },
+    Language {
+        names: &["python", "py"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in",
+            "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+            "with", "yield",
+        ],
+        literals: &["True", "False", "None"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        meta: &["@"],
+        capital_types: false,
+        lifetimes: false,
+    },
+    Language {
+        names: &["sh", "bash", "shell", "zsh"],
+        keywords: &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+            "in", "local", "return", "then", "until", "while",
+        ],
+        literals: &["true", "false"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        meta: &[],
+        capital_types: false,
+        lifetimes: false,
+    },
+    Language {
+        names: &["json"],
+        keywords: &[],
+        literals: &["true", "false", "null"],
+        line_comments: &[],
+        block_comment: None,
+        quotes: &['"'],
+        meta: &[],
+        capital_types: false,
+        lifetimes: false,
+    },
+    Language {
+        names: &["toml", "yaml", "yml"],
+        keywords: &[],
+        literals: &["true", "false", "null", "yes", "no"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        meta: &[],
+        capital_types: false,
+        lifetimes: false,
+    },
+    Language {
+        names: &["ini"],
+        keywords: &[],
+        literals: &["true", "false", "yes", "no"],
+        line_comments: &[";", "#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        meta: &[],
+        capital_types: false,
+        lifetimes: false,
+    },
+];
+
+impl Highlight {
+    /// Read how to highlight the code blocks from the configuration, returning
+    /// an error for an unknown mode or colour scheme.
+    pub fn from_config(config: &Configuration) -> Result<Highlight> {
+        let scheme = || {
+            let name = config.highlight_scheme();
+            SCHEMES.iter().find(|x| x.name == name).ok_or_else(|| {
+                ErrorKind::Fail(format!(
+                    "Unknown highlight scheme {}, expected one of {}",
+                    name,
+                    SCHEMES.iter().map(|x| x.name).collect::<Vec<_>>().join(", ")
+                ))
+            })
+        };
+        match config.highlight().as_str() {
+            "client" => Ok(Highlight::Client),
+            "classes" => Ok(Highlight::Classes(scheme()?)),
+            "inline" => Ok(Highlight::Inline(scheme()?)),
+            mode => Err(ErrorKind::Fail(format!(
+                "Unknown highlight mode {}, expected client, classes or inline",
+                mode
+            )).into()),
+        }
+    }
+
+    /// Whether highlight.js is needed within the browser
+    pub fn is_client(&self) -> bool {
+        *self == Highlight::Client
+    }
+
+    /// Whether code of the given language is highlighted at build time
+    pub fn is_highlighted(&self, lang: &str) -> bool {
+        !self.is_client() && find_language(lang).is_some()
+    }
+
+    /// Class added to the `<code>` of a code block in the given language
+    pub fn code_class(&self, lang: &str) -> Option<&'static str> {
+        match *self {
+            Highlight::Classes(_) if self.is_highlighted(lang) => Some("hljs"),
+            _ => None,
+        }
+    }
+
+    /// Style of the `<pre>` of a code block in the given language
+    pub fn pre_style(&self, lang: &str) -> Option<String> {
+        match *self {
+            Highlight::Inline(scheme) if self.is_highlighted(lang) => Some(format!(
+                "background-color: {}; color: {}",
+                scheme.background, scheme.foreground
+            )),
+            _ => None,
+        }
+    }
+
+    /// Highlight the code of the given language into escaped HTML.
+    pub fn render(&self, code: &str, lang: &str) -> String {
+        self.render_lines(code, lang).join("\n")
+    }
+
+    /// Highlight the code of the given language into the escaped HTML of each
+    /// line, closing every span by the end of its line.
+    pub fn render_lines(&self, code: &str, lang: &str) -> Vec<String> {
+        let language = match find_language(lang) {
+            Some(language) if !self.is_client() => language,
+            _ => return code.split('\n').map(escape_html).collect(),
+        };
+        let mut lines = vec![String::new()];
+        for (token, text) in tokenize(code, language) {
+            for (i, part) in text.split('\n').enumerate() {
+                if i > 0 {
+                    lines.push(String::new());
+                }
+                if part.is_empty() {
+                    continue;
+                }
+                let html = lines.last_mut().expect("No line to add to");
+                match (token, *self) {
+                    (Some(token), Highlight::Classes(_)) => html.push_str(&format!(
+                        "<span class=\"{}\">{}</span>",
+                        token.class(),
+                        escape_html(part)
+                    )),
+                    (Some(token), Highlight::Inline(scheme)) => html.push_str(&format!(
+                        "<span style=\"color: {}\">{}</span>",
+                        scheme.colour(token),
+                        escape_html(part)
+                    )),
+                    _ => html.push_str(&escape_html(part)),
+                }
+            }
+        }
+        lines
+    }
+
+    /// Stylesheet with the colours of the classes, only needed when
+    /// highlighting with classes.
+    pub fn stylesheet(&self) -> Option<String> {
+        let scheme = match *self {
+            Highlight::Classes(scheme) => scheme,
+            _ => return None,
+        };
+        let mut css = format!(
+            "/* {} colour scheme of Made-Up */\n\
+             .hljs {{\n  display: block;\n  overflow-x: auto;\n  padding: 0.5em;\n  \
+             background: {};\n  color: {};\n}}\n",
+            scheme.name, scheme.background, scheme.foreground
+        );
+        for token in &[
+            Token::Comment,
+            Token::Keyword,
+            Token::Literal,
+            Token::Meta,
+            Token::Number,
+            Token::String,
+            Token::Type,
+        ] {
+            css.push_str(&format!(
+                ".{} {{\n  color: {};\n}}\n",
+                token.class(),
+                scheme.colour(*token)
+            ));
+        }
+        Some(css)
+    }
+}
+
+fn find_language(lang: &str) -> Option<&'static Language> {
+    let lang = lang.to_lowercase();
+    LANGUAGES.iter().find(|x| x.names.contains(&lang.as_str()))
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Split the code into the text of each token, with `None` for the text in
+/// between which is not highlighted.
+fn tokenize<'c>(code: &'c str, language: &Language) -> Vec<(Option<Token>, &'c str)> {
+    let mut tokens = vec![];
+    let mut plain_start = 0;
+    let mut pos = 0;
+    while pos < code.len() {
+        let rest = &code[pos..];
+        let c = rest.chars().next().expect("Empty code remaining");
+
+        let token = if language.line_comments.iter().any(|x| rest.starts_with(x)) {
+            Some((Token::Comment, line_length(rest)))
+        } else if let Some((start, end)) = language
+            .block_comment
+            .filter(|&(start, _)| rest.starts_with(start))
+        {
+            let length = rest[start.len()..]
+                .find(end)
+                .map(|x| start.len() + x + end.len())
+                .unwrap_or(rest.len());
+            Some((Token::Comment, length))
+        } else if language.meta.iter().any(|x| rest.starts_with(x)) && at_line_start(code, pos) {
+            Some((Token::Meta, line_length(rest)))
+        } else if language.quotes.contains(&c) {
+            Some((Token::String, string_length(rest, c)))
+        } else if c == '\'' && language.lifetimes {
+            char_literal_length(rest).map(|x| (Token::String, x))
+        } else if c.is_ascii_digit() {
+            Some((Token::Number, number_length(rest)))
+        } else if is_word_char(c) {
+            let length = rest.find(|x| !is_word_char(x)).unwrap_or(rest.len());
+            let word = &rest[..length];
+            let token = if language.keywords.contains(&word) {
+                Some(Token::Keyword)
+            } else if language.literals.contains(&word) {
+                Some(Token::Literal)
+            } else if language.capital_types && word.starts_with(char::is_uppercase) {
+                Some(Token::Type)
+            } else {
+                None
+            };
+            match token {
+                Some(token) => Some((token, length)),
+                None => {
+                    pos += length;
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+
+        match token {
+            Some((token, length)) => {
+                if plain_start < pos {
+                    tokens.push((None, &code[plain_start..pos]));
+                }
+                tokens.push((Some(token), &rest[..length]));
+                pos += length;
+                plain_start = pos;
+            }
+            None => pos += c.len_utf8(),
+        }
+    }
+    if plain_start < code.len() {
+        tokens.push((None, &code[plain_start..]));
+    }
+    tokens
+}
+
+/// Whether only whitespace comes before the position within its line
+fn at_line_start(code: &str, pos: usize) -> bool {
+    let line_start = code[..pos].rfind('\n').map(|x| x + 1).unwrap_or(0);
+    code[line_start..pos].trim().is_empty()
+}
+
+/// Length of the number at the start of the text, including any suffix. A `.`
+/// is only part of the number when a digit follows, as otherwise it is more
+/// likely a method call or range.
+fn number_length(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut length = 0;
+    while length < bytes.len() {
+        let byte = bytes[length];
+        let is_decimal_point = byte == b'.'
+            && bytes.get(length + 1).is_some_and(|x| x.is_ascii_digit());
+        if !(byte.is_ascii_alphanumeric() || byte == b'_' || is_decimal_point) {
+            break;
+        }
+        length += 1;
+    }
+    length
+}
+
+/// Length up to the end of the line
+fn line_length(text: &str) -> usize {
+    text.find('\n').unwrap_or(text.len())
+}
+
+/// Length of the string starting with the quote, including the closing quote
+/// and any escaped characters. Python style triple quotes are also closed by
+/// three quotes.
+fn string_length(text: &str, quote: char) -> usize {
+    let triple: String = (0..3).map(|_| quote).collect();
+    if text.starts_with(&triple) {
+        return text[3..]
+            .find(&triple)
+            .map(|x| x + 6)
+            .unwrap_or(text.len());
+    }
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return i + 1;
+        }
+    }
+    text.len()
+}
+
+/// Length of the character literal at the start of the text, `None` if it is
+/// a lifetime instead.
+fn char_literal_length(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().skip(1);
+    match chars.next() {
+        Some((_, '\\')) => text.get(3..)?.find('\'').map(|x| x + 4),
+        Some((_, c)) => match chars.next() {
+            Some((i, '\'')) if c != '\'' => Some(i + 1),
+            _ => None,
+        },
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Highlight, Token};
+    use config::Configuration;
+
+    #[test]
+    fn test_tokenize_rust() {
+        let language = super::find_language("rust").unwrap();
+        let code = "#[derive(Debug)]\nfn main<'a>() { // Start\n    let c = '\\n';\n    \
+                    println!(\"{}\", Some(1.5), 0..2); }";
+        let tokens: Vec<(Token, &str)> = super::tokenize(code, language)
+            .into_iter()
+            .filter_map(|(token, text)| token.map(|x| (x, text)))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (Token::Meta, "#[derive(Debug)]"),
+                (Token::Keyword, "fn"),
+                (Token::Comment, "// Start"),
+                (Token::Keyword, "let"),
+                (Token::String, "'\\n'"),
+                (Token::String, "\"{}\""),
+                (Token::Type, "Some"),
+                (Token::Number, "1.5"),
+                (Token::Number, "0"),
+                (Token::Number, "2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_python() {
+        let language = super::find_language("Python").unwrap();
+        let code = "@decorator\ndef f():\n    \"\"\"Say \"hi\" \"\"\"\n    return None # Nothing";
+        let tokens: Vec<(Option<Token>, &str)> = super::tokenize(code, language);
+        assert_eq!(tokens.iter().map(|x| x.1).collect::<String>(), code);
+        assert!(tokens.contains(&(Some(Token::Meta), "@decorator")));
+        assert!(tokens.contains(&(Some(Token::String), "\"\"\"Say \"hi\" \"\"\"")));
+        assert!(tokens.contains(&(Some(Token::Literal), "None")));
+        assert!(tokens.contains(&(Some(Token::Comment), "# Nothing")));
+    }
+
+    #[test]
+    fn test_tokenize_config() {
+        let comments = |code, name| -> Vec<&str> {
+            let language = super::find_language(name).unwrap();
+            super::tokenize(code, language)
+                .into_iter()
+                .filter(|x| x.0 == Some(Token::Comment))
+                .map(|x| x.1)
+                .collect()
+        };
+        assert_eq!(comments("cmd: a; b # Run", "yaml"), vec!["# Run"]);
+        assert_eq!(comments("cmd = \"a\" ; b # c", "toml"), vec!["# c"]);
+        assert_eq!(comments("cmd = a\n; Run", "ini"), vec!["; Run"]);
+    }
+
+    #[test]
+    fn test_render() {
+        let scheme = &super::SCHEMES[0];
+        let classes = Highlight::Classes(scheme);
+        assert_eq!(
+            classes.render("let x = \"<a>\";", "rust"),
+            "<span class=\"hljs-keyword\">let</span> x = \
+             <span class=\"hljs-string\">&quot;&lt;a&gt;&quot;</span>;"
+        );
+        assert_eq!(
+            Highlight::Inline(scheme).render("true", "json"),
+            "<span style=\"color: #de935f\">true</span>"
+        );
+        assert_eq!(classes.render("let <x>", "unknown"), "let &lt;x&gt;");
+        assert_eq!(
+            classes.render_lines("/* a\nb */ c", "rust"),
+            vec![
+                "<span class=\"hljs-comment\">/* a</span>",
+                "<span class=\"hljs-comment\">b */</span> c",
+            ]
+        );
+        assert_eq!(classes.code_class("rust"), Some("hljs"));
+        assert_eq!(classes.code_class("unknown"), None);
+        assert_eq!(classes.pre_style("rust"), None);
+        assert_eq!(
+            Highlight::Inline(scheme).pre_style("rust"),
+            Some("background-color: #1d1f21; color: #c5c8c6".to_string())
+        );
+        assert!(!Highlight::Client.is_highlighted("rust"));
+        assert!(classes.stylesheet().unwrap().contains(".hljs-keyword {\n  color: #b294bb;"));
+        assert_eq!(Highlight::Inline(scheme).stylesheet(), None);
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = Configuration::default();
+        assert_eq!(Highlight::from_config(&config).unwrap(), Highlight::Client);
+        let config = Configuration::from("tests/resources/input/test_conf_highlight.yml").unwrap();
+        assert_eq!(
+            Highlight::from_config(&config).unwrap(),
+            Highlight::Inline(&super::SCHEMES[2])
+        );
+    }
+}
@@ -1,142 +1,675 @@
-use pulldown_cmark::{Event, Tag};
+use std::collections::HashMap;
 
+use pulldown_cmark::{Alignment, Event, Tag};
+
+use config::Configuration;
+use highlight::Highlight;
+use links::PageLinks;
+
+/// Paragraph which will be replaced with the table of contents of the page.
+const TOC_MARKER: &str = "<p>[TOC]";
+/// Placeholder for the table of contents while the rest of the page is rendered.
+const TOC_PLACEHOLDER: &str = "<!-- made-up:toc -->";
+
+/// HTML produced from the Markdown along with the outline of its headings.
 #[derive(Debug)]
-struct Consumer<'a, I> {
+pub struct RenderedPage {
+    content: String,
+    toc: Vec<TocEntry>,
+}
+
+impl RenderedPage {
+    /// Return the HTML of the page
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Return the table of contents for the page
+    pub fn toc(&self) -> &Vec<TocEntry> {
+        &self.toc
+    }
+}
+
+/// Heading within the table of contents of a page, holding the headings of a
+/// lower level which follow it.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TocEntry {
+    level: i32,
+    text: String,
+    id: String,
+    children: Vec<TocEntry>,
+}
+
+/// Language and attributes of a fenced code block, given by its info string
+/// such as `rust,linenos,hl_lines=3-5,title=main.rs`.
+#[derive(Debug, Default, PartialEq)]
+struct CodeBlockInfo {
+    lang: String,
+    linenos: bool,
+    // Inclusive ranges of the lines to emphasise, numbered from 1
+    hl_lines: Vec<(usize, usize)>,
+    title: Option<String>,
+    copy: bool,
+}
+
+impl CodeBlockInfo {
+    /// Parse the comma separated attributes of the info string, the first of
+    /// which is the language unless it is an attribute itself. Only the first
+    /// word of the language is used, as was the case before attributes.
+    fn parse(info: &str) -> CodeBlockInfo {
+        let mut parsed = CodeBlockInfo::default();
+        for (i, attribute) in info.split(',').map(str::trim).enumerate() {
+            if i == 0 {
+                let word = attribute.split_whitespace().next().unwrap_or("");
+                if !(word.contains('=') || word == "linenos" || word == "copy") {
+                    parsed.lang = word.to_owned();
+                    continue;
+                }
+            }
+            let mut parts = attribute.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().map(str::trim);
+            match (key, value) {
+                ("", None) => (),
+                ("linenos", None) => parsed.linenos = true,
+                ("copy", None) => parsed.copy = true,
+                ("title", Some(title)) => parsed.title = Some(title.to_owned()),
+                ("hl_lines", Some(lines)) => parsed.hl_lines = parse_line_ranges(lines),
+                _ => warn!("Ignoring unknown code block attribute {}", attribute),
+            }
+        }
+        parsed
+    }
+
+    /// Whether the line, numbered from 1, is to be emphasised
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.hl_lines
+            .iter()
+            .any(|&(start, end)| start <= line && line <= end)
+    }
+}
+
+/// Parse space separated line numbers and ranges, i.e. `1 3-5`, skipping any
+/// which are invalid.
+fn parse_line_ranges(lines: &str) -> Vec<(usize, usize)> {
+    lines
+        .split_whitespace()
+        .filter_map(|range| {
+            let mut bounds = range.splitn(2, '-').map(|x| x.trim().parse::<usize>());
+            let parsed = match (bounds.next(), bounds.next()) {
+                (Some(Ok(start)), None) => Some((start, start)),
+                (Some(Ok(start)), Some(Ok(end))) if start <= end => Some((start, end)),
+                _ => None,
+            };
+            if parsed.is_none() {
+                warn!("Ignoring invalid range of lines {} to highlight", range);
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// Which part of a table the cells currently being rendered belong to.
+#[derive(Debug, PartialEq)]
+enum TableState {
+    Head,
+    Body,
+}
+
+#[derive(Debug)]
+struct Consumer<'l, I> {
     iter: I,
+    links: &'l PageLinks<'l>,
     buffer: String,
-    current: Option<Tag<'a>>,
+    // Position within the buffer to add the ID of the current header
+    header_start: Option<usize>,
+    header_text: String,
+    used_ids: HashMap<String, usize>,
+    heading_anchors: bool,
+    headings: Vec<TocEntry>,
+    paragraph_start: Option<usize>,
+    table_state: TableState,
+    table_alignments: Vec<Alignment>,
+    table_cell_index: usize,
+    footnote_numbers: HashMap<String, usize>,
+    highlight: Highlight,
+    // Attributes and code of the code block being rendered
+    code_block: Option<(CodeBlockInfo, String)>,
 }
 
-impl<'a, I: Iterator<Item = Event<'a>>> Consumer<'a, I> {
+impl<'a, 'l, I: Iterator<Item = Event<'a>>> Consumer<'l, I> {
     /// Consume the pull parser to produce the HTML string output
     fn consume(&mut self) -> String {
         while let Some(event) = self.iter.next() {
+            if let Event::Text(ref text) = event {
+                if self.header_start.is_some() {
+                    self.header_text.push_str(text);
+                }
+            }
             match event {
-                Event::Start(tag) => {
-                    self.buffer.push_str(&print_start_elem(&tag));
-                    self.current = Some(tag);
+                Event::Start(tag) => self.start_elem(tag),
+                Event::End(tag) => self.end_elem(&tag),
+                Event::Text(text) => match self.code_block {
+                    Some((_, ref mut code)) => code.push_str(&text),
+                    None => self.buffer.push_str(&escape_html(&text)),
+                },
+                Event::Html(content) => self.buffer.push_str(&content),
+                Event::InlineHtml(content) => self.buffer.push_str(&content),
+                Event::SoftBreak => self.buffer.push('\n'),
+                Event::HardBreak => self.buffer.push_str("<br />\n"),
+                Event::FootnoteReference(name) => {
+                    let number = self.footnote_number(&name);
+                    self.buffer.push_str(&format!(
+                        "<sup class=\"footnote-reference\"><a href=\"#{}\">{}</a></sup>",
+                        escape_html(&name),
+                        number
+                    ));
                 }
-                Event::End(tag) => self.buffer.push_str(&print_end_elem(&tag)),
-                Event::Text(text) => {
-                    if let Some(tag) = self.current.clone() {
-                        match tag {
-                            // Add the ID
-                            Tag::Header(_) => {
-                                let to_add =
-                                    format!(" id=\"{}\"> {}", name_to_id(&text.to_string()), text);
-                                self.buffer.pop();
-                                self.buffer.push_str(&to_add)
-                            }
-                            Tag::Image(_, _) => self.buffer.push_str(&format!(" alt=\"{}\"", text)),
-                            _ => self.buffer.push_str(&text),
-                        }
-                    } else {
-                        self.buffer.push_str(&text)
+            }
+        }
+        self.buffer.clone()
+    }
+
+    /// Add the ID, generated from the full text of the header, to the header
+    /// which is being closed. Will also add the anchor link if enabled.
+    fn finish_header(&mut self, level: i32) {
+        if let Some(position) = self.header_start.take() {
+            let id = self.unique_id(name_to_id(&self.header_text));
+            let escaped_id = escape_html(&id);
+            self.buffer
+                .insert_str(position, &format!(" id=\"{}\"", escaped_id));
+            if self.heading_anchors {
+                self.buffer.push_str(&format!(
+                    " <a class=\"header-anchor\" href=\"#{}\">#</a>",
+                    escaped_id
+                ));
+            }
+            self.headings.push(TocEntry {
+                level,
+                text: self.header_text.trim().to_owned(),
+                id,
+                children: vec![],
+            });
+            self.header_text.clear();
+        }
+    }
+
+    /// Ensure the ID is unique within the page by adding a numbered suffix to
+    /// any which have already been used, i.e. `usage` then `usage-1`.
+    fn unique_id(&mut self, id: String) -> String {
+        let mut unique = id.clone();
+        while self.used_ids.contains_key(&unique) {
+            let count = self.used_ids.entry(id.clone()).or_insert(0);
+            *count += 1;
+            unique = format!("{}-{}", id, count);
+        }
+        self.used_ids.insert(unique.clone(), 0);
+        unique
+    }
+
+    /// Get the number for the named footnote, assigned in the order the
+    /// footnotes are first seen whether by reference or definition.
+    fn footnote_number(&mut self, name: &str) -> usize {
+        let next = self.footnote_numbers.len() + 1;
+        *self.footnote_numbers
+            .entry(name.to_owned())
+            .or_insert(next)
+    }
+
+    /// Consume the events up to the end of the current tag, returning only
+    /// the text within them. Used where markup is not allowed such as the
+    /// alternative text of an image. The text is not escaped.
+    fn consume_raw_text(&mut self) -> String {
+        let mut text = String::new();
+        let mut nest = 0;
+        while let Some(event) = self.iter.next() {
+            match event {
+                Event::Start(_) => nest += 1,
+                Event::End(_) => {
+                    if nest == 0 {
+                        break;
                     }
+                    nest -= 1;
+                }
+                Event::Text(content) | Event::InlineHtml(content) => text.push_str(&content),
+                Event::Html(_) => (),
+                Event::SoftBreak | Event::HardBreak => text.push(' '),
+                Event::FootnoteReference(name) => {
+                    let number = self.footnote_number(&name);
+                    text.push_str(&format!("[{}]", number));
                 }
-                Event::Html(content) => self.buffer.push_str(&content.to_string()),
-                Event::SoftBreak => self.buffer.push_str(" "),
-                Event::InlineHtml(content) => self.buffer.push_str(&content),
-                elem => warn!("Unhandled type: {:?}", elem),
             }
         }
-        self.buffer.clone()
+        text
+    }
+
+    /// Mapping of opening Markdown tag to HTML tag
+    fn start_elem(&mut self, tag: Tag<'a>) {
+        let html = match tag {
+            Tag::Header(int) => {
+                // Position of the closing `>` which the ID will be placed before
+                self.header_start = Some(self.buffer.len() + 3);
+                format!("<h{}>", int)
+            }
+            Tag::Strong => "<strong>".to_string(),
+            Tag::Emphasis => "<em>".to_string(),
+            Tag::Item => "<li>".to_string(),
+            Tag::List(Some(1)) => "<ol>\n".to_string(),
+            Tag::List(Some(start)) => format!("<ol start=\"{}\">\n", start),
+            Tag::List(None) => "<ul>\n".to_string(),
+            Tag::Paragraph => {
+                self.paragraph_start = Some(self.buffer.len());
+                "<p>".to_string()
+            }
+            Tag::Image(src, title) => {
+                let alt = self.consume_raw_text();
+                format!(
+                    "<img src=\"{}\" alt=\"{}\"{} />",
+                    escape_href(&src),
+                    escape_html(&alt),
+                    title_attribute(&title)
+                )
+            }
+            Tag::Code => "<code>".to_string(),
+            // The code is collected and the whole block completed at the end
+            Tag::CodeBlock(info) => {
+                self.code_block = Some((CodeBlockInfo::parse(&info), String::new()));
+                String::new()
+            }
+            Tag::Link(href, title) => format!(
+                "<a href=\"{}\"{}>",
+                escape_href(&self.links.rewrite(&href)),
+                title_attribute(&title)
+            ),
+            Tag::Table(alignments) => {
+                self.table_alignments = alignments;
+                "<table>".to_string()
+            }
+            Tag::TableHead => {
+                self.table_state = TableState::Head;
+                self.table_cell_index = 0;
+                "<thead><tr>".to_string()
+            }
+            Tag::TableRow => {
+                self.table_cell_index = 0;
+                "<tr>".to_string()
+            }
+            Tag::TableCell => {
+                let cell = if self.table_state == TableState::Head {
+                    "th"
+                } else {
+                    "td"
+                };
+                let alignment = match self.table_alignments.get(self.table_cell_index) {
+                    Some(&Alignment::Left) => " style=\"text-align: left\"",
+                    Some(&Alignment::Center) => " style=\"text-align: center\"",
+                    Some(&Alignment::Right) => " style=\"text-align: right\"",
+                    _ => "",
+                };
+                format!("<{}{}>", cell, alignment)
+            }
+            Tag::Rule => "<hr />\n".to_string(),
+            Tag::BlockQuote => "<blockquote>\n".to_string(),
+            Tag::FootnoteDefinition(name) => {
+                let number = self.footnote_number(&name);
+                format!(
+                    "<div class=\"footnote-definition\" id=\"{}\">\
+                     <sup class=\"footnote-definition-label\">{}</sup>",
+                    escape_html(&name),
+                    number
+                )
+            }
+        };
+        self.buffer.push_str(&html);
+    }
+
+    /// Mapping of closing Markdown tag to HTML tag
+    fn end_elem(&mut self, tag: &Tag) {
+        let html = match *tag {
+            Tag::Header(int) => {
+                self.finish_header(int);
+                format!("</h{}>\n", int)
+            }
+            Tag::Strong => "</strong>".to_string(),
+            Tag::Emphasis => "</em>".to_string(),
+            Tag::Item => "</li>\n".to_string(),
+            Tag::List(Some(_)) => "</ol>\n".to_string(),
+            Tag::List(None) => "</ul>\n".to_string(),
+            Tag::Paragraph => {
+                let start = self.paragraph_start.take().unwrap_or(0);
+                if self.buffer[start..] == *TOC_MARKER {
+                    self.buffer.truncate(start);
+                    format!("{}\n", TOC_PLACEHOLDER)
+                } else {
+                    "</p>\n".to_string()
+                }
+            }
+            // The image is completed at the start as it consumes its contents
+            Tag::Image(_, _) => String::new(),
+            Tag::Code => "</code>".to_string(),
+            Tag::CodeBlock(_) => match self.code_block.take() {
+                Some((info, code)) => code_block_to_html(&info, &code, self.highlight),
+                None => String::new(),
+            },
+            Tag::Link(_, _) => "</a>".to_string(),
+            Tag::Table(_) => "</tbody></table>\n".to_string(),
+            Tag::TableHead => {
+                self.table_state = TableState::Body;
+                "</tr></thead><tbody>\n".to_string()
+            }
+            Tag::TableCell => {
+                self.table_cell_index += 1;
+                if self.table_state == TableState::Head {
+                    "</th>".to_string()
+                } else {
+                    "</td>".to_string()
+                }
+            }
+            Tag::TableRow => "</tr>\n".to_string(),
+            // A rule has no content so is completed at the start
+            Tag::Rule => String::new(),
+            Tag::BlockQuote => "</blockquote>\n".to_string(),
+            Tag::FootnoteDefinition(_) => "</div>\n".to_string(),
+        };
+        self.buffer.push_str(&html);
     }
 }
 
-/// Mapping of opening Markdown tag to HTML tag
-fn print_start_elem(tag: &Tag) -> String {
-    match tag {
-        // &Tag::Header(int) => format!("<h{} id=\"", int),
-        &Tag::Header(int) => format!("<h{}>", int),
-        &Tag::Strong => "<b>".to_string(),
-        &Tag::Emphasis => "<em>".to_string(),
-        &Tag::Item => "<li>".to_string(),
-        &Tag::List(ref opt) => {
-            if opt.is_some() {
-                "<ol>".to_string()
+/// Arrange the headings into a table of contents, nesting each heading under
+/// the previous heading of a lower level. Headings outside the configured
+/// depths are left out.
+fn build_toc(headings: Vec<TocEntry>, config: &Configuration) -> Vec<TocEntry> {
+    let mut toc = vec![];
+    for heading in headings {
+        if heading.level >= config.toc_min_depth() && heading.level <= config.toc_max_depth() {
+            add_toc_entry(&mut toc, heading);
+        }
+    }
+    toc
+}
+
+/// Add the entry as the last of the siblings, or within the last sibling when
+/// it is of a lower level.
+fn add_toc_entry(siblings: &mut Vec<TocEntry>, entry: TocEntry) {
+    let nested = siblings
+        .last()
+        .map(|last| last.level < entry.level)
+        .unwrap_or(false);
+    if nested {
+        let last = siblings.last_mut().expect("Missing table of contents entry");
+        add_toc_entry(&mut last.children, entry);
+    } else {
+        siblings.push(entry);
+    }
+}
+
+/// Produce the nested list of links for the table of contents.
+fn toc_to_html(entries: &[TocEntry]) -> String {
+    if entries.is_empty() {
+        return String::new();
+    }
+    let mut html = "<ul class=\"toc\">\n".to_string();
+    for entry in entries {
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>{}</li>\n",
+            escape_html(&entry.id),
+            escape_html(&entry.text),
+            toc_to_html(&entry.children)
+        ));
+    }
+    html.push_str("</ul>");
+    html
+}
+
+/// Produce the title attribute for a link or image, empty if there is no
+/// title.
+fn title_attribute(title: &str) -> String {
+    if title.is_empty() {
+        String::new()
+    } else {
+        format!(" title=\"{}\"", escape_html(title))
+    }
+}
+
+/// Render a code block along with the line numbers, emphasised lines, title
+/// and copy button its attributes ask for. The line numbers are drawn by the
+/// stylesheet so they are not part of the code when it is copied.
+fn code_block_to_html(info: &CodeBlockInfo, code: &str, highlight: Highlight) -> String {
+    let lang = info.lang.as_str();
+    let mut classes = vec![];
+    if !lang.is_empty() {
+        classes.push(format!("language-{}", escape_html(lang)));
+    }
+    classes.extend(highlight.code_class(lang).map(str::to_owned));
+    if info.linenos {
+        classes.push("linenos".to_string());
+    }
+
+    let mut html = String::new();
+    if info.title.is_some() || info.copy {
+        html.push_str("<figure class=\"code-block\">");
+        if let Some(ref title) = info.title {
+            html.push_str(&format!(
+                "<figcaption class=\"code-title\">{}</figcaption>",
+                escape_html(title)
+            ));
+        }
+        if info.copy {
+            html.push_str("<button class=\"copy-code\" type=\"button\">Copy</button>");
+        }
+    }
+    match highlight.pre_style(lang) {
+        Some(style) => html.push_str(&format!("<pre style=\"{}\">", style)),
+        None => html.push_str("<pre>"),
+    }
+    if classes.is_empty() {
+        html.push_str("<code>");
+    } else {
+        html.push_str(&format!("<code class=\"{}\">", classes.join(" ")));
+    }
+
+    if info.linenos || !info.hl_lines.is_empty() {
+        let mut lines = highlight.render_lines(code, lang);
+        // The newline ending the last line does not start another
+        if code.ends_with('\n') {
+            lines.pop();
+        }
+        for (i, line) in lines.iter().enumerate() {
+            let class = if info.is_highlighted(i + 1) {
+                "line hl"
             } else {
-                "<ul>".to_string()
-            }
+                "line"
+            };
+            html.push_str(&format!("<span class=\"{}\">{}</span>\n", class, line));
         }
-        &Tag::Paragraph => "<p>".to_string(),
-        &Tag::Image(ref src, _) => format!("<img src=\"{}\"", src.as_ref()),
-        &Tag::Code => "<code>".to_string(),
-        &Tag::CodeBlock(ref lang) => format!("<pre><code class=\"language-{}\">", lang),
-        &Tag::Link(ref href, _) => format!("<a href=\"{}\">", href),
-        // TODO Handle alignment
-        &Tag::Table(_) => "<table>".to_string(),
-        &Tag::TableHead => "<thead>".to_string(),
-        &Tag::TableCell => "<td>".to_string(),
-        &Tag::TableRow => "<tr>".to_string(),
-        &Tag::Rule => "<hr>".to_string(),
-        &Tag::BlockQuote => "<blockquote>".to_string(),
-        tag => {
-            warn!("{:?} tag is unimplemented", tag);
-            unimplemented!();
+    } else {
+        html.push_str(&highlight.render(code, lang));
+    }
+
+    html.push_str("</code></pre>\n");
+    if info.title.is_some() || info.copy {
+        html.push_str("</figure>\n");
+    }
+    html
+}
+
+/// Escape the characters which have a special meaning in HTML so the text may
+/// be used as the content of an element or as the value of an attribute.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
         }
     }
+    escaped
 }
 
-/// Mapping of closing Markdown tag to HTML tag
-fn print_end_elem(tag: &Tag) -> String {
-    match tag {
-        &Tag::Header(int) => format!("</h{}>\n", int),
-        &Tag::Strong => "</b>\n".to_string(),
-        &Tag::Emphasis => "</em>\n".to_string(),
-        &Tag::Item => "</li>\n".to_string(),
-        &Tag::List(ref opt) => if opt.is_some() {
-            "</ol>".to_string()
-        } else {
-            "</ul>".to_string()
-        },
-        &Tag::Paragraph => "</p>\n".to_string(),
-        &Tag::Image(_, _) => "/>\n".to_string(),
-        &Tag::Code => "</code>\n".to_string(),
-        &Tag::CodeBlock(_) => "</code></pre>\n".to_string(),
-        &Tag::Link(_, _) => "</a>\n".to_string(),
-        // TODO Handle alignment
-        &Tag::Table(_) => "</table>\n".to_string(),
-        &Tag::TableHead => "</thead>\n".to_string(),
-        &Tag::TableCell => "</td>\n".to_string(),
-        &Tag::TableRow => "</tr>\n".to_string(),
-        &Tag::Rule => "</hr>".to_string(),
-        &Tag::BlockQuote => "</blockquote>".to_string(),
-        tag => {
-            warn!("{:?} tag is unimplemented", tag);
-            unimplemented!();
+/// Escape a URL so it may be used as the value of a `href` or `src`
+/// attribute. Characters which are not allowed within a URL are percent
+/// encoded while those with a special meaning in HTML are escaped.
+fn escape_href(url: &str) -> String {
+    const HEX_CHARS: &[u8] = b"0123456789ABCDEF";
+    let mut escaped = String::with_capacity(url.len());
+    for &byte in url.as_bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => escaped.push(byte as char),
+            b'!' | b'#' | b'$' | b'%' | b'(' | b')' | b'*' | b'+' | b',' | b'-' | b'.' | b'/'
+            | b':' | b';' | b'=' | b'?' | b'@' | b'_' | b'~' => escaped.push(byte as char),
+            b'&' => escaped.push_str("&amp;"),
+            b'\'' => escaped.push_str("&#x27;"),
+            _ => {
+                escaped.push('%');
+                escaped.push(HEX_CHARS[(byte >> 4) as usize] as char);
+                escaped.push(HEX_CHARS[(byte & 0xF) as usize] as char);
+            }
         }
     }
+    escaped
 }
 
-/// Convert the given string to defined standard for ID
+/// Convert the given string to defined standard for ID, matching the IDs
+/// GitHub generates for headings
 /// * All lower case
+/// * Punctuation other than hyphens and underscores removed
 /// * Spaces replaced with hyphens
 fn name_to_id(name: &str) -> String {
-    name.to_lowercase().replace(" ", "-")
+    name.trim()
+        .to_lowercase()
+        .chars()
+        .filter(|x| x.is_alphanumeric() || *x == '-' || *x == '_' || *x == ' ')
+        .map(|x| if x == ' ' { '-' } else { x })
+        .collect()
 }
 
-/// Consumes the provided `Event` iterator to produce
-/// the HTML string representation of parsed markdown.
-pub fn consume<'a, I: Iterator<Item = Event<'a>>>(iter: I) -> String {
+/// Consumes the provided `Event` iterator to produce the HTML string
+/// representation of parsed markdown along with its table of contents. A
+/// paragraph of only `[TOC]` is replaced with the table of contents and links
+/// to other Markdown files are rewritten using `links`. Code blocks are
+/// highlighted as the configuration asks, with an invalid setting left to
+/// highlight.js.
+pub fn consume<'a, I: Iterator<Item = Event<'a>>>(
+    iter: I,
+    config: &Configuration,
+    links: &PageLinks,
+) -> RenderedPage {
     let mut consumer = Consumer {
         iter,
+        links,
         buffer: String::new(),
-        current: None,
+        header_start: None,
+        header_text: String::new(),
+        used_ids: HashMap::new(),
+        heading_anchors: config.heading_anchors(),
+        headings: vec![],
+        paragraph_start: None,
+        table_state: TableState::Head,
+        table_alignments: vec![],
+        table_cell_index: 0,
+        footnote_numbers: HashMap::new(),
+        highlight: Highlight::from_config(config).unwrap_or(Highlight::Client),
+        code_block: None,
     };
-    consumer.consume()
+    let content = consumer.consume();
+    let toc = build_toc(consumer.headings, config);
+    RenderedPage {
+        content: content.replace(TOC_PLACEHOLDER, &toc_to_html(&toc)),
+        toc,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use test_utils;
+    use config::Configuration;
+    use links::SiteLinks;
+    use pulldown_cmark::Event;
+
+    /// Render the events as a page at the root of a site with no other pages
+    fn render<'a, I: Iterator<Item = Event<'a>>>(
+        iter: I,
+        config: &Configuration,
+    ) -> super::RenderedPage {
+        let site = SiteLinks::default();
+        super::consume(iter, config, &site.page("test"))
+    }
 
     #[test]
     fn test_name_to_id() {
         let actual = super::name_to_id("A very lOng name or Heading");
         assert_eq!("a-very-long-name-or-heading", actual);
         let actual = super::name_to_id("Something (with some brackets)");
-        assert_eq!("something-(with-some-brackets)", actual);
+        assert_eq!("something-with-some-brackets", actual);
+        let actual = super::name_to_id("What's `new` in v1.2?");
+        assert_eq!("whats-new-in-v12", actual);
+        let actual = super::name_to_id("snake_case & kebab-case");
+        assert_eq!("snake_case--kebab-case", actual);
+    }
+
+    #[test]
+    fn test_header_id_full_text() {
+        use pulldown_cmark::Parser;
+        let content = "## The `consume` *function*";
+        let actual = render(Parser::new(content), &Configuration::default()).content;
+        let expected = "<h2 id=\"the-consume-function\">The <code>consume</code> \
+                        <em>function</em></h2>";
+        test_utils::compare_string_content(expected, &actual);
+    }
+
+    #[test]
+    fn test_unique_header_ids() {
+        use pulldown_cmark::Parser;
+        let content = "# Usage\n## Usage\n## Usage 1\n### Usage";
+        let actual = render(Parser::new(content), &Configuration::default()).content;
+        let expected = "<h1 id=\"usage\">Usage</h1><h2 id=\"usage-1\">Usage</h2>\
+                        <h2 id=\"usage-1-1\">Usage 1</h2><h3 id=\"usage-2\">Usage</h3>";
+        test_utils::compare_string_content(expected, &actual);
+    }
+
+    #[test]
+    fn test_toc() {
+        use pulldown_cmark::Parser;
+        let content = "# Title\n## First\n### Nested\n#### Too deep\n## Second\n### Also nested";
+        let actual = render(Parser::new(content), &Configuration::default());
+        let names: Vec<(&str, usize)> = actual
+            .toc()
+            .iter()
+            .map(|x| (x.id.as_str(), x.children.len()))
+            .collect();
+        assert_eq!(names, vec![("first", 1), ("second", 1)]);
+        assert_eq!(actual.toc()[1].children[0].text, "Also nested");
+        assert_eq!(actual.toc()[1].children[0].level, 3);
+    }
+
+    #[test]
+    fn test_toc_marker() {
+        use pulldown_cmark::Parser;
+        let config = Configuration::from("tests/resources/input/test_conf_html.yml").unwrap();
+        let content = "# Title\n\n[TOC]\n\n## `First`\n\nNot a [TOC]\n\n## Second";
+        let actual = render(Parser::new(content), &config).content;
+        let expected = "<h1 id=\"title\">Title <a class=\"header-anchor\" href=\"#title\">#</a></h1>\
+                        <ul class=\"toc\"><li><a href=\"#title\">Title</a>\
+                        <ul class=\"toc\"><li><a href=\"#first\">First</a></li>\
+                        <li><a href=\"#second\">Second</a></li></ul></li></ul>\
+                        <h2 id=\"first\"><code>First</code> \
+                        <a class=\"header-anchor\" href=\"#first\">#</a></h2>\
+                        <p>Not a [TOC]</p>\
+                        <h2 id=\"second\">Second <a class=\"header-anchor\" href=\"#second\">#</a></h2>";
+        test_utils::compare_string_content(expected, &actual);
+    }
+
+    #[test]
+    fn test_heading_anchors() {
+        use pulldown_cmark::Parser;
+        let config = Configuration::from("tests/resources/input/test_conf_html.yml").unwrap();
+        let actual = render(Parser::new("# Usage"), &config).content;
+        let expected = "<h1 id=\"usage\">Usage \
+                        <a class=\"header-anchor\" href=\"#usage\">#</a></h1>";
+        test_utils::compare_string_content(expected, &actual);
     }
 
     #[test]
@@ -152,29 +685,238 @@ mod tests {
             .unwrap();
         let parser = Parser::new_ext(&content, OPTION_ENABLE_TABLES);
 
-        let actual = super::consume(parser);
+        let actual = render(parser, &Configuration::default()).content;
         let expected = include_str!("../tests/resources/output/all_test_raw_good.html");
         test_utils::compare_string_content(expected, &actual);
     }
 
+    #[test]
+    fn test_highlighted_code_block() {
+        use pulldown_cmark::Parser;
+        let config = Configuration::from("tests/resources/input/test_conf_highlight.yml").unwrap();
+        let content = "```rust\nlet x = \"<a>\";\n```\n\n```text\nlet\n```";
+        let actual = render(Parser::new(content), &config).content;
+        let expected = "<pre style=\"background-color: #fdf6e3; color: #657b83\">\
+                        <code class=\"language-rust\">\
+                        <span style=\"color: #859900\">let</span> x = \
+                        <span style=\"color: #2aa198\">&quot;&lt;a&gt;&quot;</span>;\n\
+                        </code></pre>\
+                        <pre><code class=\"language-text\">let\n</code></pre>";
+        test_utils::compare_string_content(expected, &actual);
+    }
+
+    #[test]
+    fn test_code_block_info() {
+        let info =
+            super::CodeBlockInfo::parse("rust,linenos, hl_lines=1 3-5 x 6-2,title=main.rs,copy");
+        assert_eq!(
+            info,
+            super::CodeBlockInfo {
+                lang: "rust".to_string(),
+                linenos: true,
+                hl_lines: vec![(1, 1), (3, 5)],
+                title: Some("main.rs".to_string()),
+                copy: true,
+            }
+        );
+        assert!(info.is_highlighted(4));
+        assert!(!info.is_highlighted(2));
+        assert_eq!(super::CodeBlockInfo::parse("rust ignore").lang, "rust");
+        assert_eq!(super::CodeBlockInfo::parse("ruby startline=3").lang, "ruby");
+        assert_eq!(super::CodeBlockInfo::parse("title=a.txt").lang, "");
+        assert!(super::CodeBlockInfo::parse("linenos").linenos);
+    }
+
+    #[test]
+    fn test_code_block_attributes() {
+        use pulldown_cmark::Parser;
+        let content = "```rust,linenos,hl_lines=2,title=<main>.rs,copy\nfn main() {\n}\n```";
+        let actual = render(Parser::new(content), &Configuration::default()).content;
+        let expected = "<figure class=\"code-block\">\
+                        <figcaption class=\"code-title\">&lt;main&gt;.rs</figcaption>\
+                        <button class=\"copy-code\" type=\"button\">Copy</button>\
+                        <pre><code class=\"language-rust linenos\">\
+                        <span class=\"line\">fn main() {</span>\n\
+                        <span class=\"line hl\">}</span>\n\
+                        </code></pre></figure>";
+        test_utils::compare_string_content(expected, &actual);
+    }
+
     #[test]
     fn test_ordered_list() {
         use pulldown_cmark::Parser;
         let content = "1. First point\n1. Second point";
         let parser = Parser::new(content);
 
-        let actual = super::consume(parser);
+        let actual = render(parser, &Configuration::default()).content;
         let expected = "<ol><li>First point</li><li>Second point</li></ol>";
         test_utils::compare_string_content(expected, &actual);
     }
 
+    #[test]
+    fn test_hard_break() {
+        use pulldown_cmark::Parser;
+        let content = "First line  \nSecond line\\\nThird line";
+        let parser = Parser::new(content);
+
+        let actual = render(parser, &Configuration::default()).content;
+        let expected = "<p>First line<br />Second line<br />Third line</p>";
+        test_utils::compare_string_content(expected, &actual);
+    }
+
+    #[test]
+    fn test_footnotes() {
+        use pulldown_cmark::{Parser, OPTION_ENABLE_FOOTNOTES};
+        let content = "Some text[^note] and more[^other]\n\n[^other]: The other\n\n\
+                       [^note]: The note";
+        let parser = Parser::new_ext(content, OPTION_ENABLE_FOOTNOTES);
+
+        let actual = render(parser, &Configuration::default()).content;
+        let expected = "<p>Some text<sup class=\"footnote-reference\"><a href=\"#note\">1</a></sup> \
+                        and more<sup class=\"footnote-reference\"><a href=\"#other\">2</a></sup></p>\
+                        <div class=\"footnote-definition\" id=\"other\">\
+                        <sup class=\"footnote-definition-label\">2</sup><p>The other</p></div>\
+                        <div class=\"footnote-definition\" id=\"note\">\
+                        <sup class=\"footnote-definition-label\">1</sup><p>The note</p></div>";
+        test_utils::compare_string_content(expected, &actual);
+    }
+
+    #[test]
+    fn test_table_alignment() {
+        use pulldown_cmark::{Parser, OPTION_ENABLE_TABLES};
+        let content = "| Left | Centre | Right |\n| :--- | :---: | ---: |\n| a | b | c |";
+        let parser = Parser::new_ext(content, OPTION_ENABLE_TABLES);
+
+        let actual = render(parser, &Configuration::default()).content;
+        let expected = "<table><thead><tr><th style=\"text-align: left\">Left</th>\
+                        <th style=\"text-align: center\">Centre</th>\
+                        <th style=\"text-align: right\">Right</th></tr></thead><tbody>\
+                        <tr><td style=\"text-align: left\">a</td>\
+                        <td style=\"text-align: center\">b</td>\
+                        <td style=\"text-align: right\">c</td></tr></tbody></table>";
+        test_utils::compare_string_content(expected, &actual);
+    }
+
+    #[test]
+    fn test_header_starting_with_code() {
+        use pulldown_cmark::Parser;
+        let content = "# `main` function";
+        let parser = Parser::new(content);
+
+        let actual = render(parser, &Configuration::default()).content;
+        let expected = "<h1 id=\"main-function\"><code>main</code> function</h1>";
+        test_utils::compare_string_content(expected, &actual);
+    }
+
+    /// Removes the IDs added to headers, which are not part of CommonMark.
+    fn strip_header_ids(html: &str) -> String {
+        let mut result = String::new();
+        let mut rest = html;
+        while let Some(index) = rest.find(" id=\"") {
+            let is_header = rest[..index].len() >= 3 && {
+                let tag = &rest[index - 3..index];
+                tag.starts_with("<h") && tag.ends_with(|x: char| x.is_ascii_digit())
+            };
+            result.push_str(&rest[..index]);
+            rest = &rest[index..];
+            if is_header {
+                let end = rest[5..].find('"').map(|x| x + 6).unwrap_or(rest.len());
+                rest = &rest[end..];
+            } else {
+                result.push(' ');
+                rest = &rest[1..];
+            }
+        }
+        result.push_str(rest);
+        result
+    }
+
+    /// Examples of the CommonMark specification, by their number, which are
+    /// known not to match the HTML it expects.
+    const KNOWN_FAILURES: &[usize] = &[
+        // Setext headings and HTML blocks are parsed differently by pulldown-cmark
+        51, 64, 116, 130, 131, 132, 133, 134, 135, 137, 138, 139, 143, 144, 146, 147, 149, 298, 308,
+        // Link reference definitions are parsed differently by pulldown-cmark
+        161, 167, 175,
+        // List items are parsed differently by pulldown-cmark
+        225, 229, 242, 243, 248, 267, 269, 270, 347,
+        // Nested emphasis is parsed differently by pulldown-cmark
+        391, 394, 406, 444, 445, 447,
+        // Links, images and autolinks are parsed differently by pulldown-cmark
+        464, 468, 473, 486, 500, 513, 514, 520, 527, 539, 558, 569, 570, 572,
+        // Raw HTML is parsed differently by pulldown-cmark
+        588,
+    ];
+
+    /// Checks every example within the specification file renders to the
+    /// expected HTML, reporting all examples which do not other than those
+    /// known to fail.
+    fn check_spec_examples(path: &str, known_failures: &[usize]) {
+        use pulldown_cmark::Parser;
+        let examples = test_utils::read_spec_examples(path);
+        assert!(!examples.is_empty());
+        let failures: Vec<_> = examples
+            .iter()
+            .filter(|example| {
+                let parser = Parser::new(&example.markdown);
+                let actual = render(parser, &Configuration::default()).content;
+                let actual = strip_header_ids(&actual);
+                !test_utils::same_string_content(&example.html, &actual)
+            })
+            .collect();
+        let unexpected: Vec<_> = failures
+            .iter()
+            .filter(|x| !known_failures.contains(&x.number))
+            .collect();
+        assert!(
+            unexpected.is_empty(),
+            "{} of {} examples failed: {:#?}",
+            unexpected.len(),
+            examples.len(),
+            unexpected
+        );
+        // Keep the list of known failures from hiding regressions
+        let fixed: Vec<_> = known_failures
+            .iter()
+            .filter(|&&x| !failures.iter().any(|y| y.number == x))
+            .collect();
+        assert!(
+            fixed.is_empty(),
+            "Examples {:?} now pass, remove them from the known failures",
+            fixed
+        );
+    }
+
+    #[test]
+    fn test_commonmark_spec() {
+        let examples = "tests/resources/commonmark/spec.txt";
+        assert_eq!(test_utils::read_spec_examples(examples).len(), 624);
+        check_spec_examples(examples, KNOWN_FAILURES);
+    }
+
+    #[test]
+    fn test_escaping() {
+        check_spec_examples("tests/resources/commonmark/escaping.txt", &[]);
+    }
+
+    #[test]
+    fn test_escape_header() {
+        use pulldown_cmark::Parser;
+        let parser = Parser::new("# Say \"hi\" & bye");
+        let actual = render(parser, &Configuration::default()).content;
+        test_utils::compare_string_content(
+            "<h1 id=\"say-hi--bye\">Say &quot;hi&quot; &amp; bye</h1>",
+            &actual,
+        );
+    }
+
     #[test]
     fn test_unordered_list() {
         use pulldown_cmark::Parser;
         let content = "- First point\n- Second point";
         let parser = Parser::new(content);
 
-        let actual = super::consume(parser);
+        let actual = render(parser, &Configuration::default()).content;
         let expected = "<ul><li>First point</li><li>Second point</li></ul>";
         test_utils::compare_string_content(expected, &actual);
     }
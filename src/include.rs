@@ -0,0 +1,397 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use file_utils;
+use {ErrorKind, Result};
+
+/// Start of the directive which is replaced by the content of another file.
+const DIRECTIVE: &str = "{{#include";
+const DIRECTIVE_END: &str = "}}";
+/// Markers of the region of a file which can be included by its name.
+const ANCHOR: &str = "ANCHOR:";
+const ANCHOR_END: &str = "ANCHOR_END:";
+
+/// Part of a file to include.
+#[derive(Debug, PartialEq)]
+enum Selection<'a> {
+    All,
+    /// Inclusive range of lines numbered from 1, the end being open if `None`
+    Lines(usize, Option<usize>),
+    Anchor(&'a str),
+}
+
+/// Replace every `{{#include path}}` directive within the Markdown of a page
+/// with the content of the file at `path`, relative to the directory of the
+/// page. The path may be followed by a range of lines, i.e. `file.rs:10:40`,
+/// `file.rs:10:` or `file.rs::40`, a single line as `file.rs:10` or the name
+/// of an anchor as `file.rs:setup` to include the lines between the
+/// `ANCHOR: setup` and `ANCHOR_END: setup` comments of the file. Lines with
+/// anchor comments are never included. A directive starting with `\` is left
+/// as it is without the `\`.
+///
+/// Included Markdown files have their own directives expanded, relative to
+/// themselves. Returns the expanded content along with every file included,
+/// directly or not, so that the page can be rebuilt when they change, and
+/// the origin of each line of the content.
+///
+/// Returns an error with the page and line of the directive when the file can
+/// not be read, does not have the anchor or includes itself.
+pub fn expand(content: &str, page: &Path) -> Result<(String, Vec<PathBuf>, Vec<Origin>)> {
+    let mut includer = Includer {
+        stack: vec![file_utils::normalize_path(page)],
+        dependencies: vec![],
+    };
+    let expanded = includer.expand(content, page)?;
+    let mut dependencies = includer.dependencies;
+    dependencies.sort();
+    dependencies.dedup();
+    Ok((expanded.content, dependencies, expanded.origins))
+}
+
+/// File and line a line of the expanded content was written on. A line which
+/// an included file is inserted within keeps the origin of its start.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Origin {
+    pub path: PathBuf,
+    /// Line of the file, numbered from 1
+    pub line: usize,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}", self.path.display(), self.line)
+    }
+}
+
+/// Content with its directives expanded and the origin of each of its lines.
+#[derive(Debug, Default)]
+struct Expanded {
+    content: String,
+    origins: Vec<Origin>,
+}
+
+impl Expanded {
+    /// Append the text, taking the origins of the lines it starts in turn. Its
+    /// first line continues the last line of the content if that is not ended.
+    fn push<I: IntoIterator<Item = Origin>>(&mut self, text: &str, origins: I) {
+        for (line, origin) in text.split_inclusive('\n').zip(origins) {
+            if self.content.is_empty() || self.content.ends_with('\n') {
+                self.origins.push(origin);
+            }
+            self.content.push_str(line);
+        }
+    }
+}
+
+/// Expands the directives of a page and the Markdown files it includes.
+#[derive(Debug)]
+struct Includer {
+    // Files being expanded, starting with the page, to detect cycles
+    stack: Vec<PathBuf>,
+    dependencies: Vec<PathBuf>,
+}
+
+impl Includer {
+    fn expand(&mut self, content: &str, file: &Path) -> Result<Expanded> {
+        // Origins of the lines of the content from the one `position` is on
+        let origins = |position: usize| {
+            let first = content[..position].matches('\n').count() + 1;
+            (first..).map(move |line| Origin {
+                path: file.to_path_buf(),
+                line,
+            })
+        };
+        let mut expanded = Expanded::default();
+        let mut position = 0;
+        while let Some(found) = content[position..].find(DIRECTIVE) {
+            let start = position + found;
+            if content[..start].ends_with('\\') {
+                expanded.push(&content[position..start - 1], origins(position));
+                expanded.push(DIRECTIVE, origins(start));
+                position = start + DIRECTIVE.len();
+                continue;
+            }
+            let end = match content[start..].find(DIRECTIVE_END) {
+                Some(end) => start + end,
+                None => break,
+            };
+            expanded.push(&content[position..start], origins(position));
+            let argument = content[start + DIRECTIVE.len()..end].trim();
+            let included = self.include(argument, file).map_err(|err| {
+                ErrorKind::Fail(format!(
+                    "Unable to include {} in {} at line {}: {}",
+                    argument,
+                    file.display(),
+                    content[..start].matches('\n').count() + 1,
+                    err
+                ))
+            })?;
+            expanded.push(&included.content, included.origins);
+            position = end + DIRECTIVE_END.len();
+        }
+        expanded.push(&content[position..], origins(position));
+        Ok(expanded)
+    }
+
+    /// Read the part of the file the argument of the directive selects, with
+    /// the reason it could not be included on failure.
+    fn include(&mut self, argument: &str, file: &Path) -> ::std::result::Result<Expanded, String> {
+        let mut parts = argument.splitn(2, ':');
+        let path = parts.next().unwrap_or("").trim();
+        if path.is_empty() {
+            return Err("no file given".to_string());
+        }
+        let selection = parse_selection(parts.next())?;
+        let full_path = file_utils::normalize_path(
+            &file.parent().unwrap_or_else(|| Path::new("")).join(path),
+        );
+        if self.stack.contains(&full_path) {
+            let cycle: Vec<String> = self.stack
+                .iter()
+                .chain(Some(&full_path))
+                .map(|x| x.display().to_string())
+                .collect();
+            return Err(format!("it includes itself through {}", cycle.join(" -> ")));
+        }
+        self.dependencies.push(full_path.clone());
+        let content = file_utils::read_from_file(&full_path)
+            .map_err(|err| format!("could not read {}: {}", full_path.display(), err))?;
+        let (included, lines) = select(&content, &selection, path)?;
+        if !is_markdown(&full_path) {
+            let mut expanded = Expanded::default();
+            expanded.push(
+                &included,
+                lines.into_iter().map(|line| Origin {
+                    path: full_path.clone(),
+                    line,
+                }),
+            );
+            return Ok(expanded);
+        }
+
+        self.stack.push(full_path.clone());
+        let expanded = self.expand(&included, &full_path);
+        self.stack.pop();
+        let mut expanded = expanded.map_err(|err| match *err.kind() {
+            ErrorKind::Fail(ref message) => message.clone(),
+            ref kind => kind.to_string(),
+        })?;
+        // The selected lines were numbered from the first one selected
+        for origin in expanded.origins.iter_mut().filter(|x| x.path == full_path) {
+            origin.line = lines[origin.line - 1];
+        }
+        Ok(expanded)
+    }
+}
+
+/// Lines of the content the selection asks for, without any anchor comments,
+/// along with the number of each of them
+fn select(
+    content: &str,
+    selection: &Selection,
+    path: &str,
+) -> ::std::result::Result<(String, Vec<usize>), String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let (first, selected) = match *selection {
+        Selection::All => (1, &lines[..]),
+        Selection::Lines(start, end) => {
+            let end = end.unwrap_or(lines.len()).min(lines.len());
+            if start > end {
+                return Err(format!("{} has no line {}", path, start));
+            }
+            (start, &lines[start - 1..end])
+        }
+        Selection::Anchor(name) => {
+            let start = lines
+                .iter()
+                .position(|x| anchor_name(x, ANCHOR) == Some(name))
+                .ok_or_else(|| format!("{} has no anchor {}", path, name))?;
+            let end = lines[start..]
+                .iter()
+                .position(|x| anchor_name(x, ANCHOR_END) == Some(name))
+                .map_or(lines.len(), |x| start + x);
+            (start + 2, &lines[start + 1..end])
+        }
+    };
+    let (numbers, kept): (Vec<usize>, Vec<&str>) = selected
+        .iter()
+        .enumerate()
+        .filter(|&(_, x)| anchor_name(x, ANCHOR).is_none() && anchor_name(x, ANCHOR_END).is_none())
+        .map(|(i, x)| (first + i, *x))
+        .unzip();
+    Ok((kept.join("\n"), numbers))
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.eq_ignore_ascii_case("md"))
+        .unwrap_or(false)
+}
+
+/// Parse what follows the path of the directive.
+fn parse_selection(selection: Option<&str>) -> ::std::result::Result<Selection<'_>, String> {
+    let selection = match selection.map(str::trim) {
+        None | Some("") => return Ok(Selection::All),
+        Some(selection) => selection,
+    };
+    let line = |x: &str| -> ::std::result::Result<Option<usize>, String> {
+        match x.trim() {
+            "" => Ok(None),
+            number => match number.parse::<usize>() {
+                Ok(0) | Err(_) => Err(format!("invalid line number {}", number)),
+                Ok(number) => Ok(Some(number)),
+            },
+        }
+    };
+    let mut bounds = selection.splitn(2, ':');
+    let start = bounds.next().unwrap_or("");
+    match bounds.next() {
+        Some(end) => Ok(Selection::Lines(line(start)?.unwrap_or(1), line(end)?)),
+        None if start.chars().all(|x| x.is_ascii_digit()) => {
+            let start = line(start)?.unwrap_or(1);
+            Ok(Selection::Lines(start, Some(start)))
+        }
+        None => Ok(Selection::Anchor(start)),
+    }
+}
+
+/// Name of the anchor the line marks with `marker`, if any
+fn anchor_name<'l>(line: &'l str, marker: &str) -> Option<&'l str> {
+    line.find(marker)
+        .and_then(|x| line[x + marker.len()..].split_whitespace().next())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use super::{Origin, Selection};
+    use {Error, ErrorKind};
+
+    const PAGE: &str = "tests/resources/input/include/page.md";
+
+    fn expand(content: &str) -> String {
+        super::expand(content, Path::new(PAGE)).unwrap().0
+    }
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(super::parse_selection(None), Ok(Selection::All));
+        assert_eq!(super::parse_selection(Some("3")), Ok(Selection::Lines(3, Some(3))));
+        assert_eq!(super::parse_selection(Some("3:")), Ok(Selection::Lines(3, None)));
+        assert_eq!(super::parse_selection(Some(":4")), Ok(Selection::Lines(1, Some(4))));
+        assert_eq!(super::parse_selection(Some("2:4")), Ok(Selection::Lines(2, Some(4))));
+        assert_eq!(super::parse_selection(Some("main")), Ok(Selection::Anchor("main")));
+        assert!(super::parse_selection(Some("0:4")).is_err());
+        assert!(super::parse_selection(Some("1:x")).is_err());
+    }
+
+    #[test]
+    fn test_include_lines() {
+        assert_eq!(
+            expand("```rust\n{{#include source.rs:1:2}}\n```"),
+            "```rust\nuse std::env;\n\n```"
+        );
+        assert_eq!(
+            expand("{{#include source.rs:4}}"),
+            "fn main() {"
+        );
+        assert_eq!(
+            expand("{{#include source.rs:10:}}"),
+            "    println!(\"{}\", args.len());\n}"
+        );
+    }
+
+    #[test]
+    fn test_include_anchor() {
+        assert_eq!(
+            expand("Before {{ #include source.rs:args }} after"),
+            "Before {{ #include source.rs:args }} after"
+        );
+        assert_eq!(
+            expand("{{#include source.rs:args}}"),
+            "    let args: Vec<String> = env::args().collect();"
+        );
+        // The anchor comments are left out of the whole file
+        assert!(!expand("{{#include source.rs}}").contains("ANCHOR"));
+    }
+
+    #[test]
+    fn test_escaped_directive() {
+        assert_eq!(
+            expand("\\{{#include source.rs}} and {{#include source.rs:1}}"),
+            "{{#include source.rs}} and use std::env;"
+        );
+    }
+
+    /// Message of the error from expanding the content
+    fn expand_error(content: &str) -> String {
+        match super::expand(content, Path::new(PAGE)) {
+            Err(Error(ErrorKind::Fail(message), _)) => message,
+            other => panic!("Expected to fail but got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let message = expand_error("# Page\n\n{{#include missing.rs}}");
+        assert!(message.contains(PAGE));
+        assert!(message.contains("line 3"));
+        assert!(message.contains("missing.rs"));
+
+        assert!(expand_error("{{#include source.rs:missing}}").contains("no anchor missing"));
+    }
+
+    #[test]
+    fn test_include_markdown() {
+        let (content, dependencies, _) = super::expand(
+            "# Page\n\n{{#include _shared/warning.md}}",
+            Path::new(PAGE),
+        ).unwrap();
+        assert_eq!(
+            content,
+            "# Page\n\n> **Warning** run `main` with care:\n>\n> fn main() {"
+        );
+        let dir = Path::new("tests/resources/input/include");
+        assert_eq!(
+            dependencies,
+            vec![dir.join("_shared/warning.md"), dir.join("source.rs")]
+        );
+    }
+
+    #[test]
+    fn test_include_origins() {
+        let (content, _, origins) = super::expand(
+            "# Page\n\n{{#include _shared/warning.md}}\n[[Missing]]",
+            Path::new(PAGE),
+        ).unwrap();
+        assert_eq!(content.lines().count(), origins.len());
+        let origin = |path: &str, line| Origin {
+            path: PathBuf::from(path),
+            line,
+        };
+        let warning = "tests/resources/input/include/_shared/warning.md";
+        assert_eq!(
+            origins,
+            vec![
+                origin(PAGE, 1),
+                origin(PAGE, 2),
+                origin(warning, 1),
+                origin(warning, 2),
+                origin(warning, 3),
+                origin(PAGE, 4),
+            ]
+        );
+        assert_eq!(origins[5].to_string(), format!("{} at line 4", PAGE));
+
+        let (_, _, origins) = super::expand("{{#include source.rs:args}}", Path::new(PAGE)).unwrap();
+        assert_eq!(origins, vec![origin("tests/resources/input/include/source.rs", 6)]);
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let message = expand_error("{{#include _shared/cycle.md}}");
+        assert!(message.contains("includes itself"));
+        assert!(message.contains("cycle.md -> tests/resources/input/include/page.md"));
+    }
+}
@@ -10,16 +10,34 @@ extern crate serde_json;
 extern crate serde_yaml;
 extern crate walkdir;
 
-use std::fs::{self, File};
-use std::io::Read;
+use std::collections::HashMap;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 use walker::{MarkdownFile, MarkdownFileList};
+use manifest::Manifest;
+
+pub use server::{LiveReload, Server};
 
 mod html;
 mod walker;
 mod file_utils;
 mod config;
 mod templates;
+mod theme;
+mod front_matter;
+mod highlight;
+mod include;
+mod links;
+mod manifest;
+mod order;
+mod server;
+mod taxonomy;
+mod watcher;
+mod wiki;
 
 #[cfg(test)]
 mod test_utils;
@@ -38,10 +56,32 @@ error_chain! {
     }
 }
 
+/// Name of the configuration file within the root directory.
+const CONFIG_NAME: &str = "mdup.yml";
+/// Time between checks for changes to the files of the site when watching.
+const WATCH_INTERVAL_MS: u64 = 500;
+
 #[derive(Debug)]
 pub struct Convertor {
     configuration: config::Configuration,
     root_dir: PathBuf,
+    // Output directory given in place of the configured one
+    out_dir: Option<String>,
+    live_reload: bool,
+    // Whether the previous build failed, possibly leaving pages unwritten, so
+    // the next rebuild must cover the whole site
+    failed: bool,
+    force: bool,
+    // Include the pages which are drafts, or outside of their dates
+    drafts: bool,
+    future: bool,
+    theme: theme::Theme,
+    // Templates compiled once and shared by every page
+    templates: templates::Templates,
+    // Number of threads to render pages on
+    jobs: usize,
+    // Manifest of the build in progress, saved once its files are written
+    manifest: Mutex<Manifest>,
 }
 
 #[derive(Debug)]
@@ -62,74 +102,377 @@ impl Convertor {
         let configuration = read_config(&root_dir)?;
         debug!("{:?}", configuration);
         handle_config(&root_dir, &configuration)?;
+        let theme = theme::Theme::load(&root_dir, &configuration.theme())?;
+        let templates = load_templates(&root_dir, &configuration, &theme)?;
         Ok(Convertor {
             configuration,
             root_dir,
+            out_dir: None,
+            live_reload: false,
+            failed: false,
+            force: false,
+            drafts: false,
+            future: false,
+            theme,
+            templates,
+            jobs: thread::available_parallelism().map(|x| x.get()).unwrap_or(1),
+            manifest: Mutex::new(Manifest::default()),
         })
     }
 
+    /// Returns the directory the site will be written to
+    pub fn out_dir(&self) -> String {
+        self.configuration.out_dir()
+    }
+
+    /// Write the site to `out_dir` rather than the configured output directory.
+    pub fn set_out_dir<P: AsRef<Path>>(&mut self, out_dir: P) {
+        let out_dir = out_dir.as_ref().to_string_lossy().into_owned();
+        self.configuration.set_out_dir(out_dir.clone());
+        self.out_dir = Some(out_dir);
+    }
+
+    /// Include the live reload script within each generated page, for when
+    /// the site is being served while watching for changes.
+    pub fn set_live_reload(&mut self, live_reload: bool) {
+        self.live_reload = live_reload;
+        self.templates.set_live_reload(live_reload);
+    }
+
+    /// Render and write every page, ignoring what was recorded about the
+    /// previous build of the site.
+    pub fn set_force(&mut self, force: bool) {
+        self.force = force;
+    }
+
+    /// Include the pages marked as a `draft` in their front matter
+    pub fn set_drafts(&mut self, drafts: bool) {
+        self.drafts = drafts;
+    }
+
+    /// Include the pages whose `date` is in the future or which have passed
+    /// their `expires` date.
+    pub fn set_future(&mut self, future: bool) {
+        self.future = future;
+    }
+
+    /// Render the pages of the site on `jobs` threads, defaulting to the
+    /// number of CPUs available.
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = jobs.max(1);
+    }
+
     /// Entry function which will perform the entire process for the static site
     /// generation.
     ///
     /// Through here it will:
     ///
     /// * Find all markdown files to use
-    /// * Convert all to HTML
+    /// * Convert all to HTML, mirroring the directory structure of the root
+    ///   directory in the output directory
+    ///
+    /// Pages are skipped when neither their source, the configuration nor the
+    /// templates have changed since the previous build. Drafts, and pages
+    /// dated in the future or which have expired, are left out of the site
+    /// unless they were asked for.
     pub fn generate_site(&self) -> Result<Vec<ConvertedFile>> {
+        let all_files = self.find_all_files()?;
+        self.generate_pages(&all_files, |_| true)
+    }
+
+    /// Convert the files of the site accepted by `include` which have changed
+    /// since the previous build, along with the index page when any of the
+    /// headings it lists have changed.
+    fn generate_pages<F: Fn(&MarkdownFile) -> bool>(
+        &self,
+        all_files: &MarkdownFileList,
+        include: F,
+    ) -> Result<Vec<ConvertedFile>> {
         let mut converted_files = vec![];
 
-        let all_files = find_all_files(&self.root_dir)?;
+        check_output_collisions(all_files)?;
+        let site_links = links::SiteLinks::new(all_files);
 
-        let out_dir = self.configuration.out_dir();
+        let out_dir = PathBuf::from(self.configuration.out_dir());
+        let previous = if self.force {
+            Manifest::default()
+        } else {
+            Manifest::load(&out_dir)
+        };
+        let mut manifest = previous.next_build(self.settings_hash());
 
+        let mut to_render = vec![];
         for file in all_files.get_files() {
-            let result = create_html(file, &self.configuration)?;
+            let path = out_dir.join(file.get_output_path());
+            let content = file.read_source()?;
+            let source = manifest::hash(&content);
+            let name = output_name(&out_dir, &path);
+            let changed = previous.page_changed(&mut manifest, &name, source)
+                || !file_utils::check_file_exists(&path);
+            // Pages are also rendered again once the targets of their links
+            // appear or disappear, or the pages linking to them change, even
+            // when excluded from a rebuild
+            let link_path = file.get_link_path();
+            let targets = manifest::hash(&site_links.page(&link_path).targets(&content));
+            let backlinks = site_links.backlinks(&link_path);
+            let relinked = previous.links_changed(&mut manifest, &name, targets);
+            let backlinked = previous.backlinks_changed(&mut manifest, &name, backlinks);
+            if !(include(file) && changed || relinked || backlinked) {
+                debug!("Skipping unchanged {}", file.get_path().display());
+                continue;
+            }
+            to_render.push(file);
+        }
+        let rendered = self.render_pages(&to_render, &site_links)?;
+        for (file, content) in to_render.iter().zip(rendered) {
             converted_files.push(ConvertedFile {
-                path: PathBuf::from(&out_dir).join(format!("{}.html", file.get_file_name())),
-                content: result,
+                path: out_dir.join(file.get_output_path()),
+                content,
             })
         }
-        let index_content = match self.configuration.index_template() {
-            Some(index_path) => {
-                // Generate it from what we have been given
-                debug!("Using user defined index template");
-                let template_path = self.root_dir.join(index_path);
-                templates::render_index_with_template(
-                    template_path,
-                    &all_files,
-                    &self.configuration,
-                )?
-            }
-            None => {
-                debug!("Using default index template");
-                templates::generate_index(&all_files, &self.configuration)?
+
+        // The index lists the heading of every page
+        let index_path = out_dir.join("index.html");
+        let headings: Vec<(String, String)> = all_files
+            .get_files()
+            .iter()
+            .map(|x| (x.get_link_path(), x.get_heading()))
+            .collect();
+        if previous.index_changed(&mut manifest, manifest::hash(&headings))
+            || !file_utils::check_file_exists(&index_path)
+        {
+            converted_files.push(ConvertedFile {
+                path: index_path,
+                content: self.generate_index(all_files)?,
+            });
+        } else {
+            debug!("Skipping unchanged index");
+        }
+        converted_files.extend(self.generate_taxonomies(all_files, &previous, &mut manifest)?);
+
+        // A forced build still removes the outputs left by the last build
+        if self.force {
+            manifest.remove_missing(&Manifest::load(&out_dir));
+        } else {
+            manifest.remove_missing(&previous);
+        }
+        *self.manifest.lock().expect("Build manifest poisoned") = manifest;
+        Ok(converted_files)
+    }
+
+    /// Starting at the root directory, find all Markdown files within it which
+    /// are published, along with the drafts and the pages outside of their
+    /// dates when those are included, in the order configured for the site.
+    /// Returns an error if the front matter of a file or the order is not
+    /// valid.
+    fn find_all_files(&self) -> Result<MarkdownFileList> {
+        let today = front_matter::today();
+        let mut files = vec![];
+        for file in walker::find_markdown_files(&self.root_dir)? {
+            debug!("{:?}", file);
+            let front_matter = file.get_front_matter()?;
+            let invalid_date = |err| {
+                ErrorKind::Fail(format!("Invalid date in {}: {}", file.get_path().display(), err))
+            };
+            let scheduled = front_matter.is_scheduled(&today).map_err(&invalid_date)?;
+            let expired = front_matter.is_expired(&today).map_err(&invalid_date)?;
+            if front_matter.is_draft() && !self.drafts {
+                debug!("Leaving out the draft {}", file.get_path().display());
+            } else if (scheduled || expired) && !self.future {
+                debug!("Leaving out {}, it is not within its dates", file.get_path().display());
+            } else {
+                files.push(file);
             }
+        }
+        let order = order::PageOrder::load(&self.root_dir, &self.configuration, &files)?;
+        Ok(MarkdownFileList::new(files).sorted(&order))
+    }
+
+    /// Render the pages across the worker threads. The pages are returned in
+    /// the order given, and when several fail the error of the first is
+    /// returned, so the result does not depend on how the work was shared.
+    fn render_pages(
+        &self,
+        files: &[&MarkdownFile],
+        site_links: &links::SiteLinks,
+    ) -> Result<Vec<String>> {
+        let render = |file: &MarkdownFile| {
+            create_html(file, &self.configuration, site_links, &self.templates)
         };
+        let jobs = self.jobs.min(files.len());
+        if jobs <= 1 {
+            return files.iter().map(|x| render(x)).collect();
+        }
 
-        converted_files.push(ConvertedFile {
-            path: PathBuf::from(&out_dir).join("index.html"),
-            content: index_content,
+        debug!("Rendering {} pages on {} threads", files.len(), jobs);
+        let next = AtomicUsize::new(0);
+        let results: Vec<Mutex<Option<Result<String>>>> =
+            files.iter().map(|_| Mutex::new(None)).collect();
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    if index >= files.len() {
+                        break;
+                    }
+                    let result = render(files[index]);
+                    *results[index].lock().expect("Render result poisoned") = Some(result);
+                });
+            }
         });
+        results
+            .into_iter()
+            .map(|x| {
+                x.into_inner()
+                    .expect("Render result poisoned")
+                    .expect("Page was not rendered")
+            })
+            .collect()
+    }
 
+    /// Render the pages of each taxonomy which any page has terms for: one
+    /// listing its terms and one for each term listing the pages given it.
+    /// The pages are skipped when the terms of the taxonomy are unchanged.
+    fn generate_taxonomies(
+        &self,
+        all_files: &MarkdownFileList,
+        previous: &Manifest,
+        manifest: &mut Manifest,
+    ) -> Result<Vec<ConvertedFile>> {
+        let out_dir = PathBuf::from(self.configuration.out_dir());
+        let mut converted_files = vec![];
+        for name in self.configuration.taxonomies() {
+            let taxonomy = taxonomy::Taxonomy::collect(&name, all_files)?;
+            if taxonomy.terms().is_empty() {
+                continue;
+            }
+            check_taxonomy_collisions(all_files, &taxonomy)?;
+
+            let path = out_dir.join(taxonomy.index_path());
+            let name = output_name(&out_dir, &path);
+            if previous.page_changed(manifest, &name, manifest::hash(&taxonomy))
+                || !file_utils::check_file_exists(&path)
+            {
+                let content = self.templates
+                    .render_taxonomy(&taxonomy, &self.configuration)?;
+                converted_files.push(ConvertedFile { path, content });
+            }
+            for term in taxonomy.terms() {
+                let path = out_dir.join(taxonomy.term_path(term));
+                let name = output_name(&out_dir, &path);
+                if previous.page_changed(manifest, &name, manifest::hash(term))
+                    || !file_utils::check_file_exists(&path)
+                {
+                    let content = self.templates
+                        .render_term(&taxonomy, term, &self.configuration)?;
+                    converted_files.push(ConvertedFile { path, content });
+                }
+            }
+        }
         Ok(converted_files)
     }
 
+    /// Render the index page listing every page of the site
+    fn generate_index(&self, all_files: &MarkdownFileList) -> Result<String> {
+        self.templates.render_index(all_files, &self.configuration)
+    }
+
+    /// Hash of everything, other than its source, which affects the output of
+    /// a page. A change to any of these requires every page to be rendered.
+    fn settings_hash(&self) -> u64 {
+        manifest::hash(&(
+            format!("{:?}", self.configuration),
+            self.live_reload,
+            self.templates.fingerprint(),
+        ))
+    }
+
+    /// Rebuild the site after the files in `changed` were created, modified or
+    /// removed. When only Markdown files of the site changed just their pages
+    /// and the index are regenerated. A change to the configuration rereads it
+    /// before rebuilding the whole site, as does any other change or a change
+    /// following a failed build.
+    pub fn rebuild(&mut self, changed: &[PathBuf]) -> Result<()> {
+        if changed.contains(&self.root_dir.join(CONFIG_NAME)) {
+            info!("Configuration changed, rebuilding the site");
+            let mut configuration = read_config(&self.root_dir)?;
+            handle_config(&self.root_dir, &configuration)?;
+            if let Some(ref out_dir) = self.out_dir {
+                configuration.set_out_dir(out_dir.clone());
+            }
+            self.configuration = configuration;
+            self.reload_templates()?;
+            let files = self.generate_site()?;
+            return self.write_files(files);
+        }
+
+        let all_files = self.find_all_files()?;
+        // A page is affected by changes to it and to the files it includes
+        let affects = |file: &MarkdownFile, path: &PathBuf| {
+            file.get_path() == path || file.depends_on(path)
+        };
+        let pages_only = !self.failed
+            && changed
+                .iter()
+                .all(|path| all_files.get_files().iter().any(|x| affects(x, path)));
+        if pages_only {
+            debug!("Regenerating pages affected by {:?}", changed);
+            let files = self.generate_pages(&all_files, |x| {
+                changed.iter().any(|path| affects(x, path))
+            })?;
+            self.write_pages(files)
+        } else {
+            info!("Rebuilding the site");
+            // The index template may have been one of the changes
+            self.reload_templates()?;
+            let files = self.generate_pages(&all_files, |_| true)?;
+            self.write_files(files)
+        }
+    }
+
+    /// Load the theme and compile the templates again to pick up changes to
+    /// the configuration or the templates
+    fn reload_templates(&mut self) -> Result<()> {
+        self.theme = theme::Theme::load(&self.root_dir, &self.configuration.theme())?;
+        self.templates = load_templates(&self.root_dir, &self.configuration, &self.theme)?;
+        self.templates.set_live_reload(self.live_reload);
+        Ok(())
+    }
+
+    /// Build the site and then watch the root directory, rebuilding the site
+    /// as its files change until the process is stopped. The outcome of the
+    /// first build and of each rebuild is given to `on_build` so that errors
+    /// can be reported without stopping.
+    pub fn watch<F: FnMut(Result<()>)>(&mut self, mut on_build: F) {
+        let mut watcher = watcher::Watcher::new(&self.root_dir, self.configuration.out_dir());
+        let mut result = self.generate_site().and_then(|files| self.write_files(files));
+        info!("Watching {} for changes", self.root_dir.display());
+        loop {
+            self.failed = result.is_err();
+            on_build(result);
+            let mut changed = watcher.changes();
+            while changed.is_empty() {
+                thread::sleep(Duration::from_millis(WATCH_INTERVAL_MS));
+                changed = watcher.changes();
+            }
+            result = self.rebuild(&changed);
+        }
+    }
+
     const IMAGE_DIR: &'static str = "images";
     /// Write the files provided to the file system
     ///
     /// The files provided will already be produced using `generate_site` and
-    /// hence have all configuration information present
+    /// hence have all configuration information present. Files are only
+    /// written when their content differs from the previous build.
     pub fn write_files(&self, files: Vec<ConvertedFile>) -> Result<()> {
-        if !file_utils::check_dir_exists(self.configuration.out_dir()) {
-            fs::create_dir(self.configuration.out_dir())?;
-        }
-        for file in files {
-            file_utils::write_to_file(file.path, file.content)?;
-        }
+        self.write_pages(files)?;
+        let mut manifest = self.manifest.lock().expect("Build manifest poisoned");
+        let out_dir = PathBuf::from(self.configuration.out_dir());
         if self.configuration.copy_resources() {
             for stylesheet in &self.configuration.stylesheet() {
                 // Copy across the stylesheet
-                file_utils::copy_file(&self.root_dir, &self.configuration.out_dir(), stylesheet)?;
+                self.copy_output(&mut manifest, &self.root_dir, &out_dir, stylesheet)?;
             }
 
             // Copy across the images
@@ -140,7 +483,8 @@ impl Convertor {
             for entry in fs::read_dir(&images_source)? {
                 let entry = entry?;
                 debug!("Copying {:?}", entry.file_name());
-                file_utils::copy_file(
+                self.copy_output(
+                    &mut manifest,
                     &images_source,
                     &images_dest,
                     &entry
@@ -151,56 +495,179 @@ impl Convertor {
             }
         }
 
-        // Write across the default stylings. Need static string so can be
-        // within the binary.
-        let highlight_css = include_str!("../theme/highlight.css");
-        let highlight_js = include_str!("../theme/highlight.js");
-        let made_up_css = include_str!("../theme/made-up.css");
-        let tomorrow_night_css = include_str!("../theme/tomorrow-night.css");
-
-        file_utils::write_to_file(
-            self.configuration.out_dir() + "/highlight.css",
-            highlight_css.to_owned(),
-        )?;
-        file_utils::write_to_file(
-            self.configuration.out_dir() + "/highlight.js",
-            highlight_js.to_owned(),
-        )?;
-        file_utils::write_to_file(
-            self.configuration.out_dir() + "/made-up.css",
-            made_up_css.to_owned(),
-        )?;
-        file_utils::write_to_file(
-            self.configuration.out_dir() + "/tomorrow-night.css",
-            tomorrow_night_css.to_owned(),
-        )?;
+        // Write across the files of the theme, unless the user has their own
+        // file of the same name.
+        let user_files = if self.configuration.copy_resources() {
+            self.configuration.stylesheet()
+        } else {
+            vec![]
+        };
+        for (name, content) in self.theme.assets(self.configuration.use_cdn())? {
+            if !user_files.contains(&name) {
+                self.write_output(&mut manifest, out_dir.join(name), &content)?;
+            }
+        }
+        let highlight = highlight::Highlight::from_config(&self.configuration)?;
+        if let Some(stylesheet) = highlight.stylesheet() {
+            let path = out_dir.join(highlight::STYLESHEET_NAME);
+            self.write_output(&mut manifest, path, stylesheet.as_bytes())?;
+        }
 
+        manifest.save(&out_dir)
+    }
+
+    /// Write the generated pages without copying any of the resources
+    fn write_pages(&self, files: Vec<ConvertedFile>) -> Result<()> {
+        if !file_utils::check_dir_exists(self.configuration.out_dir()) {
+            fs::create_dir(self.configuration.out_dir())?;
+        }
+        let mut manifest = self.manifest.lock().expect("Build manifest poisoned");
+        for file in files {
+            self.write_output(&mut manifest, file.path, file.content.as_bytes())?;
+        }
+        manifest.prune(self.configuration.out_dir())?;
+        manifest.save(self.configuration.out_dir())
+    }
+
+    /// Write the content to the output file unless it is the same as the
+    /// previous build wrote.
+    fn write_output(&self, manifest: &mut Manifest, path: PathBuf, content: &[u8]) -> Result<()> {
+        let name = output_name(&PathBuf::from(self.configuration.out_dir()), &path);
+        if manifest.output_changed(&name, manifest::hash(content))
+            || !file_utils::check_file_exists(&path)
+        {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, content)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the file across to the output unless it is the same as the file
+    /// copied by the previous build.
+    fn copy_output<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        manifest: &mut Manifest,
+        source_dir: &P,
+        dest_dir: &Q,
+        file_name: &str,
+    ) -> Result<()> {
+        let dest = dest_dir.as_ref().join(file_name);
+        let name = output_name(&PathBuf::from(self.configuration.out_dir()), &dest);
+        let content = fs::read(source_dir.as_ref().join(file_name))?;
+        if manifest.output_changed(&name, manifest::hash(&content[..]))
+            || !file_utils::check_file_exists(&dest)
+        {
+            file_utils::copy_file(source_dir, dest_dir, file_name)?;
+        }
         Ok(())
     }
 }
 
-/// Starting at the root directory provided, find all Markdown files within in.
-fn find_all_files<P: AsRef<Path>>(root_dir: P) -> Result<MarkdownFileList> {
-    let files = walker::find_markdown_files(root_dir)?;
-    for file in &files {
-        debug!("{:?}", file);
+/// Name of an output file within the manifest, its path within the output
+/// directory.
+fn output_name(out_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(out_dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Ensures that no two Markdown files will be written to the same output file,
+/// such as `setup.md` and `setup.MD` within the same directory.
+fn check_output_collisions(files: &MarkdownFileList) -> Result<()> {
+    let mut outputs: HashMap<PathBuf, &PathBuf> = HashMap::new();
+    for file in files.get_files() {
+        if let Some(existing) = outputs.insert(file.get_output_path(), file.get_path()) {
+            return Err(ErrorKind::Fail(format!(
+                "Both {} and {} would be written to {}",
+                existing.display(),
+                file.get_path().display(),
+                file.get_output_path().display()
+            )).into());
+        }
     }
-    Ok(MarkdownFileList::new(files))
+    Ok(())
+}
+
+/// Ensures that none of the pages of the taxonomy would be written over the
+/// page of a Markdown file, such as a `tags/rust.md` with the `rust` tag.
+fn check_taxonomy_collisions(
+    files: &MarkdownFileList,
+    taxonomy: &taxonomy::Taxonomy,
+) -> Result<()> {
+    let mut outputs = vec![taxonomy.index_path()];
+    outputs.extend(taxonomy.terms().iter().map(|x| taxonomy.term_path(x)));
+    for file in files.get_files() {
+        if outputs.contains(&file.get_output_path()) {
+            return Err(ErrorKind::Fail(format!(
+                "The {} pages would be written over {} at {}",
+                taxonomy.name(),
+                file.get_path().display(),
+                file.get_output_path().display()
+            )).into());
+        }
+    }
+    Ok(())
 }
 
 /// Converts the provided Markdown file to it HTML equivalent. This ia a direct
-/// mapping it does not add more tags, such as `<body>` or `<html>`.
-fn create_html(file: &MarkdownFile, config: &config::Configuration) -> Result<String> {
-    let mut content = String::new();
-    File::open(file.get_path()).and_then(|mut x| x.read_to_string(&mut content))?;
-    let parser = pulldown_cmark::Parser::new_ext(&content, pulldown_cmark::OPTION_ENABLE_TABLES);
+/// mapping it does not add more tags, such as `<body>` or `<html>`. Any front
+/// matter is removed before the conversion and provided to the template. Links
+/// to other Markdown files, and wiki links, are resolved against the pages in
+/// `site_links`.
+fn create_html(
+    file: &MarkdownFile,
+    config: &config::Configuration,
+    site_links: &links::SiteLinks,
+    templates: &templates::Templates,
+) -> Result<String> {
+    let (front_matter, content, origins) = file.read_content()?;
+    let link_path = file.get_link_path();
+    let page_links = site_links.page(&link_path);
+    let content = wiki::rewrite(&content, &page_links, &origins);
+    let parser = pulldown_cmark::Parser::new_ext(
+        &content,
+        pulldown_cmark::OPTION_ENABLE_TABLES | pulldown_cmark::OPTION_ENABLE_FOOTNOTES,
+    );
+
+    let page = html::consume(parser, config, &page_links);
+    templates.encapsulate_bare_html(
+        &page,
+        page_links.backlinks(),
+        config,
+        file.get_heading(),
+        &file.get_site_root(),
+        &front_matter,
+    )
+}
 
-    templates::encapsulate_bare_html(html::consume(parser), config, file.get_heading())
+/// Compile the templates for the site, using the index template and the
+/// templates directory given in the configuration when there are any. The
+/// templates of the user replace those of the theme.
+fn load_templates(
+    root_dir: &Path,
+    config: &config::Configuration,
+    theme: &theme::Theme,
+) -> Result<templates::Templates> {
+    let index_template = match config.index_template() {
+        Some(index_path) => {
+            debug!("Using user defined index template");
+            Some(file_utils::read_from_file(root_dir.join(index_path))?)
+        }
+        None => None,
+    };
+    let templates_dirs: Vec<PathBuf> = theme
+        .templates_dir()
+        .into_iter()
+        .chain(config.templates_dir().map(|x| root_dir.join(x)))
+        .collect();
+    templates::Templates::new(index_template.as_deref(), &templates_dirs)
 }
 
 /// Finds the configuration file and deserializes it.
 fn read_config<P: AsRef<Path>>(path: P) -> Result<config::Configuration> {
-    const CONFIG_NAME: &str = "mdup.yml";
     let full_path = path.as_ref().to_path_buf();
     debug!(
         "Starting search for configuration file at: {:?}",
@@ -224,6 +691,8 @@ fn read_config<P: AsRef<Path>>(path: P) -> Result<config::Configuration> {
 /// Processes the configuration and ensure the environment is in a state
 /// matching the definition in the configuration. This function will ensure:
 /// * When the index template is specified, that the specified file exists.
+/// * When the templates directory is specified, that the directory exists.
+/// * That the name of each taxonomy can be used as the name of its directory.
 fn handle_config(root_dir: &AsRef<Path>, config: &config::Configuration) -> Result<()> {
     if config.index_template().is_some() {
         let path = root_dir.as_ref().join(
@@ -241,16 +710,37 @@ fn handle_config(root_dir: &AsRef<Path>, config: &config::Configuration) -> Resu
             ).into());
         }
     }
+    if let Some(templates_dir) = config.templates_dir() {
+        let path = root_dir.as_ref().join(templates_dir);
+        if !file_utils::check_dir_exists(&path) {
+            return Err(ErrorKind::Fail(format!(
+                "Did not find templates directory {} specified in configuration",
+                path.display()
+            )).into());
+        }
+    }
+    highlight::Highlight::from_config(config)?;
+    order::SortBy::from_config(config)?;
+    for name in config.taxonomies() {
+        if taxonomy::slugify(&name) != name {
+            return Err(ErrorKind::Fail(format!(
+                "Taxonomy name {:?} in configuration must only be lowercase letters, \
+                 digits and hyphens",
+                name
+            )).into());
+        }
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use test_utils;
+    use file_utils;
     use std::env;
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::path::Path;
-    use super::MarkdownFile;
+    use super::{MarkdownFile, MarkdownFileList};
 
     #[test]
     fn test_create_html() {
@@ -259,10 +749,370 @@ mod tests {
             super::config::Configuration::from("tests/resources/input/site/mdup.yml").unwrap();
         let expected = include_str!("../tests/resources/output/all_test_good.html");
         let md_file = MarkdownFile::from(Path::new("tests/resources/input/site/all_test.md"));
-        let actual = super::create_html(&md_file, &config).unwrap();
+        let actual = super::create_html(
+            &md_file,
+            &config,
+            &super::links::SiteLinks::default(),
+            &super::templates::Templates::new(None, &[]).unwrap(),
+        ).unwrap();
         test_utils::compare_string_content(expected, &actual);
     }
 
+    #[test]
+    fn test_create_nested_html() {
+        let config =
+            super::config::Configuration::from("tests/resources/input/site/mdup.yml").unwrap();
+        let md_file = MarkdownFile::from_root(
+            Path::new("tests/resources/input/site"),
+            Path::new("tests/resources/input/site/nested/nested-page.md"),
+        );
+        let actual = super::create_html(
+            &md_file,
+            &config,
+            &super::links::SiteLinks::default(),
+            &super::templates::Templates::new(None, &[]).unwrap(),
+        ).unwrap();
+        assert!(actual.contains("href=\"../highlight.css\""));
+        assert!(actual.contains("href=\"../index.html\""));
+        assert!(actual.contains("href=\"../style.css\""));
+        assert!(actual.contains("href=\"../second-page.html#top\""));
+    }
+
+    #[test]
+    fn test_create_html_front_matter() {
+        let config =
+            super::config::Configuration::from("tests/resources/input/site/mdup.yml").unwrap();
+        let md_file = MarkdownFile::from(Path::new("tests/resources/input/front-matter.md"));
+        let actual = super::create_html(
+            &md_file,
+            &config,
+            &super::links::SiteLinks::default(),
+            &super::templates::Templates::new(None, &[]).unwrap(),
+        ).unwrap();
+        assert!(!actual.contains("description:"));
+        assert!(actual.contains("<title>Title - Page With Front Matter</title>"));
+        assert!(actual.contains("<meta name=\"description\" content=\"A page to test front matter\">"));
+    }
+
+    // Ensure that two sources mapping to the same output is an error
+    #[test]
+    fn test_output_collision() {
+        let root = Path::new("site");
+        let files = MarkdownFileList::new(vec![
+            MarkdownFile::from_root(root, Path::new("site/nested/setup.md")),
+            MarkdownFile::from_root(root, Path::new("site/setup.md")),
+            MarkdownFile::from_root(root, Path::new("site/nested/setup.MD")),
+        ]);
+        assert!(super::check_output_collisions(&files).is_err());
+
+        let files = MarkdownFileList::new(vec![
+            MarkdownFile::from_root(root, Path::new("site/nested/setup.md")),
+            MarkdownFile::from_root(root, Path::new("site/setup.md")),
+        ]);
+        assert!(super::check_output_collisions(&files).is_ok());
+    }
+
+    // Ensure only the changed pages and the index are rewritten on a rebuild
+    #[test]
+    fn test_rebuild() {
+        let root = test_utils::temp_dir("rebuild-test");
+        let out = root.join("out");
+        fs::create_dir_all(root.join("images")).unwrap();
+        file_utils::write_to_file(root.join("mdup.yml"), "title: Rebuild".to_string()).unwrap();
+        file_utils::write_to_file(root.join("first.md"), "# First".to_string()).unwrap();
+        file_utils::write_to_file(root.join("second.md"), "# Second".to_string()).unwrap();
+        let mut convertor = super::Convertor::new(&root).unwrap();
+        convertor.set_out_dir(&out);
+        convertor.set_live_reload(true);
+
+        file_utils::write_to_file(root.join("first.md"), "# First Changed".to_string()).unwrap();
+        convertor.rebuild(&[root.join("first.md")]).unwrap();
+        let first = file_utils::read_from_file(out.join("first.html")).unwrap();
+        assert!(first.contains("First Changed"));
+        assert!(first.contains("/__made-up/live-reload.js"));
+        assert!(file_utils::read_from_file(out.join("index.html"))
+            .unwrap()
+            .contains("First Changed"));
+        assert!(!file_utils::check_file_exists(out.join("second.html")));
+
+        // Anything other than a page rebuilds the whole site
+        File::create(root.join("style.css")).unwrap();
+        convertor.rebuild(&[root.join("style.css")]).unwrap();
+        assert!(file_utils::check_file_exists(out.join("second.html")));
+        assert!(file_utils::check_file_exists(out.join("made-up.css")));
+
+        // Changing an included snippet regenerates only the pages using it
+        fs::create_dir_all(root.join("_shared")).unwrap();
+        file_utils::write_to_file(root.join("_shared/note.md"), "Noted".to_string()).unwrap();
+        let second = "# Second\n\n{{#include _shared/note.md}}".to_string();
+        file_utils::write_to_file(root.join("second.md"), second).unwrap();
+        convertor
+            .rebuild(&[root.join("_shared/note.md"), root.join("second.md")])
+            .unwrap();
+        fs::remove_file(out.join("made-up.css")).unwrap();
+        file_utils::write_to_file(root.join("_shared/note.md"), "Changed".to_string()).unwrap();
+        convertor.rebuild(&[root.join("_shared/note.md")]).unwrap();
+        assert!(file_utils::read_from_file(out.join("second.html"))
+            .unwrap()
+            .contains("Changed"));
+        assert!(!file_utils::check_file_exists(out.join("made-up.css")));
+
+        // Changing the links of a page regenerates the pages it links to
+        fs::create_dir_all(root.join("nested")).unwrap();
+        file_utils::write_to_file(root.join("nested/third.md"), "# Third".to_string()).unwrap();
+        let first = "# First\n\n[Third](nested/third.md)".to_string();
+        file_utils::write_to_file(root.join("first.md"), first).unwrap();
+        convertor
+            .rebuild(&[root.join("nested/third.md"), root.join("first.md")])
+            .unwrap();
+        assert!(file_utils::read_from_file(out.join("nested/third.html"))
+            .unwrap()
+            .contains("<a href=\"../first.html\">First</a>"));
+        file_utils::write_to_file(root.join("first.md"), "# First".to_string()).unwrap();
+        convertor.rebuild(&[root.join("first.md")]).unwrap();
+        assert!(!file_utils::read_from_file(out.join("nested/third.html"))
+            .unwrap()
+            .contains("Linked from"));
+    }
+
+    // Ensure the whole site is rebuilt after a failed build, as pages other
+    // than those changed may not have been written
+    #[test]
+    fn test_rebuild_after_failure() {
+        let root = test_utils::temp_dir("failed-rebuild-test");
+        let out = root.join("out");
+        fs::create_dir_all(root.join("images")).unwrap();
+        file_utils::write_to_file(root.join("mdup.yml"), "title: Rebuild".to_string()).unwrap();
+        file_utils::write_to_file(root.join("first.md"), "# First".to_string()).unwrap();
+        file_utils::write_to_file(root.join("second.md"), "# Second".to_string()).unwrap();
+        let mut convertor = super::Convertor::new(&root).unwrap();
+        convertor.set_out_dir(&out);
+
+        convertor.failed = true;
+        convertor.rebuild(&[root.join("first.md")]).unwrap();
+        assert!(file_utils::check_file_exists(out.join("first.html")));
+        assert!(file_utils::check_file_exists(out.join("second.html")));
+    }
+
+    // Ensure drafts and pages outside of their dates are only built when asked
+    #[test]
+    fn test_unpublished_pages() {
+        let root = test_utils::temp_dir("unpublished-test");
+        let write = |name: &str, content: &str| {
+            file_utils::write_to_file(root.join(name), content.to_string()).unwrap()
+        };
+        write("mdup.yml", "title: Unpublished");
+        write("published.md", "---\ndate: 2000-01-01\n---\n# Published");
+        write("draft.md", "---\ndraft: true\n---\n# Draft");
+        write("future.md", "---\ndate: 2999-01-01\n---\n# Future");
+        write("expired.md", "---\nexpires: 2000-01-01\n---\n# Expired");
+        let mut convertor = super::Convertor::new(&root).unwrap();
+        convertor.set_out_dir(root.join("out"));
+        convertor.set_force(true);
+
+        let generated = |convertor: &super::Convertor| {
+            let files = convertor.generate_site().unwrap();
+            let index = &files.last().unwrap().content;
+            assert_eq!(index.matches("<li>").count(), files.len() - 1);
+            files.len()
+        };
+        assert_eq!(generated(&convertor), 2);
+        convertor.set_drafts(true);
+        assert_eq!(generated(&convertor), 3);
+        convertor.set_drafts(false);
+        convertor.set_future(true);
+        assert_eq!(generated(&convertor), 4);
+
+        write("expired.md", "---\nexpires: next week\n---\n# Expired");
+        match convertor.generate_site() {
+            Err(super::Error(super::ErrorKind::Fail(message), _)) => {
+                assert!(message.contains("expired.md"))
+            }
+            other => panic!("Expected an invalid date but got {:?}", other),
+        }
+    }
+
+    // Ensure unchanged pages are skipped unless the build is forced
+    #[test]
+    fn test_incremental_build() {
+        let root = test_utils::temp_dir("incremental-test");
+        let out = root.join("out");
+        fs::create_dir_all(root.join("images")).unwrap();
+        file_utils::write_to_file(root.join("mdup.yml"), "title: Incremental".to_string()).unwrap();
+        file_utils::write_to_file(root.join("first.md"), "# First".to_string()).unwrap();
+        file_utils::write_to_file(root.join("second.md"), "# Second".to_string()).unwrap();
+        let mut convertor = super::Convertor::new(&root).unwrap();
+        convertor.set_out_dir(&out);
+        let files = convertor.generate_site().unwrap();
+        assert_eq!(files.len(), 3);
+        convertor.write_files(files).unwrap();
+        assert!(file_utils::check_file_exists(out.join(super::manifest::MANIFEST_NAME)));
+
+        // Nothing changed so nothing is rendered
+        assert!(convertor.generate_site().unwrap().is_empty());
+
+        // Only the changed page is rendered, the headings are the same
+        file_utils::write_to_file(root.join("first.md"), "# First\nMore".to_string()).unwrap();
+        let files = convertor.generate_site().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, out.join("first.html"));
+        convertor.write_files(files).unwrap();
+
+        // A changed heading renders the index as well
+        file_utils::write_to_file(root.join("second.md"), "# Renamed".to_string()).unwrap();
+        assert_eq!(convertor.generate_site().unwrap().len(), 2);
+
+        convertor.set_force(true);
+        assert_eq!(convertor.generate_site().unwrap().len(), 3);
+    }
+
+    // Ensure a page is rendered again once the target of its link appears
+    #[test]
+    fn test_link_target_added() {
+        let root = test_utils::temp_dir("link-target-test");
+        let out = root.join("out");
+        fs::create_dir_all(root.join("images")).unwrap();
+        file_utils::write_to_file(root.join("mdup.yml"), "title: Links".to_string()).unwrap();
+        let first = "# First\n[Second](second.md)".to_string();
+        file_utils::write_to_file(root.join("first.md"), first).unwrap();
+        let mut convertor = super::Convertor::new(&root).unwrap();
+        convertor.set_out_dir(&out);
+        convertor.write_files(convertor.generate_site().unwrap()).unwrap();
+
+        file_utils::write_to_file(root.join("second.md"), "# Second".to_string()).unwrap();
+        let files = convertor.generate_site().unwrap();
+        assert!(files.iter().any(|x| x.path == out.join("first.html")));
+    }
+
+    // Ensure a page is rendered again once its wiki link matches another page
+    #[test]
+    fn test_wiki_target_renamed() {
+        let root = test_utils::temp_dir("wiki-target-test");
+        let out = root.join("out");
+        fs::create_dir_all(root.join("images")).unwrap();
+        file_utils::write_to_file(root.join("mdup.yml"), "title: Wiki".to_string()).unwrap();
+        let first = "# First\n[[Getting Started]]".to_string();
+        file_utils::write_to_file(root.join("first.md"), first).unwrap();
+        file_utils::write_to_file(root.join("second.md"), "# Second".to_string()).unwrap();
+        let mut convertor = super::Convertor::new(&root).unwrap();
+        convertor.set_out_dir(&out);
+        convertor.write_files(convertor.generate_site().unwrap()).unwrap();
+
+        let second = "# Getting Started".to_string();
+        file_utils::write_to_file(root.join("second.md"), second).unwrap();
+        let files = convertor.generate_site().unwrap();
+        assert!(files.iter().any(|x| x.path == out.join("first.html")));
+    }
+
+    #[test]
+    fn test_removed_page() {
+        let root = test_utils::temp_dir("removed-test");
+        let out = root.join("out");
+        fs::create_dir_all(root.join("images")).unwrap();
+        file_utils::write_to_file(root.join("mdup.yml"), "title: Removed".to_string()).unwrap();
+        file_utils::write_to_file(root.join("first.md"), "# First".to_string()).unwrap();
+        file_utils::write_to_file(root.join("second.md"), "# Second".to_string()).unwrap();
+        let mut convertor = super::Convertor::new(&root).unwrap();
+        convertor.set_out_dir(&out);
+        convertor.write_files(convertor.generate_site().unwrap()).unwrap();
+        assert!(file_utils::check_file_exists(out.join("second.html")));
+
+        // The output of a removed source goes along with it
+        fs::remove_file(root.join("second.md")).unwrap();
+        convertor.write_files(convertor.generate_site().unwrap()).unwrap();
+        assert!(file_utils::check_file_exists(out.join("first.html")));
+        assert!(!file_utils::check_file_exists(out.join("second.html")));
+        let manifest =
+            file_utils::read_from_file(out.join(super::manifest::MANIFEST_NAME)).unwrap();
+        assert!(!manifest.contains("second.html"));
+    }
+
+    // Ensure the page of a source which becomes a draft is removed
+    #[test]
+    fn test_draft_removed() {
+        let root = test_utils::temp_dir("draft-test");
+        let out = root.join("out");
+        fs::create_dir_all(root.join("images")).unwrap();
+        file_utils::write_to_file(root.join("mdup.yml"), "title: Draft".to_string()).unwrap();
+        file_utils::write_to_file(root.join("first.md"), "# First".to_string()).unwrap();
+        file_utils::write_to_file(root.join("second.md"), "# Second".to_string()).unwrap();
+        let mut convertor = super::Convertor::new(&root).unwrap();
+        convertor.set_out_dir(&out);
+        convertor.write_files(convertor.generate_site().unwrap()).unwrap();
+        assert!(file_utils::check_file_exists(out.join("second.html")));
+
+        let second = "---\ndraft: true\n---\n# Second".to_string();
+        file_utils::write_to_file(root.join("second.md"), second).unwrap();
+        convertor.rebuild(&[root.join("second.md")]).unwrap();
+        assert!(file_utils::check_file_exists(out.join("first.html")));
+        assert!(!file_utils::check_file_exists(out.join("second.html")));
+        let manifest =
+            file_utils::read_from_file(out.join(super::manifest::MANIFEST_NAME)).unwrap();
+        assert!(!manifest.contains("second.html"));
+    }
+
+    // Ensure each taxonomy has a page listing its terms and one for each term
+    #[test]
+    fn test_taxonomies() {
+        let mut convertor = super::Convertor::new("tests/resources/input/taxonomy").unwrap();
+        let out = env::temp_dir().join("made-up-taxonomy-test");
+        convertor.set_out_dir(&out);
+        convertor.set_force(true);
+        let files = convertor.generate_site().unwrap();
+        let content = |path: &str| {
+            files
+                .iter()
+                .find(|x| x.path == out.join(path))
+                .map(|x| x.content.clone())
+                .unwrap_or_else(|| panic!("{} was not generated", path))
+        };
+        // Two pages, the index, and both taxonomies with their terms
+        assert_eq!(files.len(), 10);
+        let tags = content("tags/index.html");
+        assert!(tags.contains("<a href=\"getting-started.html\">Getting Started</a>"));
+        assert!(tags.contains("<a href=\"_index.html\">Index</a>"));
+        assert!(content("tags/_index.html").contains("../guides/setup.html"));
+        assert!(tags.contains("<span class=\"badge\">2</span>"));
+        let rust = content("tags/rust.html");
+        assert!(rust.contains("<title>Taxonomies - Rust</title>"));
+        assert!(rust.contains("<a href=\"../intro.html\">Introduction</a>"));
+        assert!(!rust.contains("Setting Up"));
+        assert!(content("authors/bob.html").contains("../guides/setup.html"));
+    }
+
+    #[test]
+    fn test_taxonomy_collision() {
+        let root = Path::new("tests/resources/input/taxonomy");
+        let intro = || MarkdownFile::from_root(root, &root.join("intro.md"));
+        let files = MarkdownFileList::new(vec![intro()]);
+        let tags = super::taxonomy::Taxonomy::collect("tags", &files).unwrap();
+        let authors = super::taxonomy::Taxonomy::collect("authors", &files).unwrap();
+
+        let files = MarkdownFileList::new(vec![
+            intro(),
+            MarkdownFile::from_root(root, &root.join("tags/rust.md")),
+        ]);
+        assert!(super::check_taxonomy_collisions(&files, &tags).is_err());
+        assert!(super::check_taxonomy_collisions(&files, &authors).is_ok());
+    }
+
+    // Ensure rendering on several threads gives the same pages in the same order
+    #[test]
+    fn test_parallel_render() {
+        let mut convertor = super::Convertor::new("tests/resources/input/site").unwrap();
+        convertor.set_out_dir(env::temp_dir().join("made-up-parallel-test"));
+        convertor.set_force(true);
+        convertor.set_jobs(1);
+        let sequential = convertor.generate_site().unwrap();
+        convertor.set_jobs(4);
+        let parallel = convertor.generate_site().unwrap();
+        assert_eq!(sequential.len(), parallel.len());
+        for (expected, actual) in sequential.iter().zip(parallel.iter()) {
+            assert_eq!(expected.path, actual.path);
+            assert_eq!(expected.content, actual.content);
+        }
+    }
+
     // Ensure that will return an error when no configuration found
     #[test]
     fn test_fail_read_config() {
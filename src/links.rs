@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use pulldown_cmark::{Event, Parser, Tag};
+use pulldown_cmark::{OPTION_ENABLE_FOOTNOTES, OPTION_ENABLE_TABLES};
+
+use walker::MarkdownFileList;
+use wiki;
+
+/// Extension of the Markdown files which links are rewritten for.
+const MARKDOWN_EXTENSION: &str = ".md";
+/// Extension of the pages produced for each Markdown file.
+const HTML_EXTENSION: &str = ".html";
+
+/// All of the pages within the site, used to resolve the links written
+/// between Markdown files to the pages which are generated for them.
+#[derive(Debug, Default)]
+pub struct SiteLinks {
+    // Link paths of every page, such as `nested/setup`
+    pages: HashSet<String>,
+    // Link paths of the pages by the wiki name of their link path, of their
+    // file and of their heading, i.e. `getting-started`
+    paths: HashMap<String, BTreeSet<String>>,
+    file_names: HashMap<String, BTreeSet<String>>,
+    headings: HashMap<String, BTreeSet<String>>,
+    // Pages linking to each page, by the link path of the page linked to
+    backlinks: HashMap<String, Vec<Backlink>>,
+}
+
+/// Page which links to another page of the site, provided to the page
+/// template of the page it links to.
+#[derive(Serialize, Debug, Clone, Hash, PartialEq)]
+pub struct Backlink {
+    header: String,
+    file_path: String,
+}
+
+impl SiteLinks {
+    pub fn new(files: &MarkdownFileList) -> SiteLinks {
+        let mut paths: HashMap<String, BTreeSet<String>> = HashMap::new();
+        let mut file_names: HashMap<String, BTreeSet<String>> = HashMap::new();
+        let mut headings: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for file in files.get_files() {
+            paths
+                .entry(wiki_name(&file.get_link_path()))
+                .or_default()
+                .insert(file.get_link_path());
+            file_names
+                .entry(wiki_name(&file.get_file_name()))
+                .or_default()
+                .insert(file.get_link_path());
+            headings
+                .entry(wiki_name(&file.get_heading()))
+                .or_default()
+                .insert(file.get_link_path());
+        }
+        let mut site = SiteLinks {
+            pages: files.get_files().iter().map(|x| x.get_link_path()).collect(),
+            paths,
+            file_names,
+            headings,
+            backlinks: HashMap::new(),
+        };
+
+        // Pages which can not be read link to nothing, failing when rendered
+        for file in files.get_files() {
+            let link_path = file.get_link_path();
+            let linked = match file.read_content() {
+                Ok((_, content, _)) => site.page(&link_path).linked_pages(&content),
+                Err(_) => continue,
+            };
+            for target in linked {
+                site.backlinks.entry(target).or_default().push(Backlink {
+                    header: file.get_heading(),
+                    file_path: link_path.clone(),
+                });
+            }
+        }
+        site
+    }
+
+    /// Pages which link to the page with the given link path, in the order of
+    /// the pages of the site.
+    pub fn backlinks(&self, link_path: &str) -> &[Backlink] {
+        self.backlinks
+            .get(link_path)
+            .map(|x| x.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Link paths of the pages a wiki link could refer to, in order. The
+    /// target is matched against the link path of each page, then the name of
+    /// its file and then its heading, ignoring case and treating spaces,
+    /// hyphens and underscores alike. Several pages are only returned when
+    /// they match in the same way.
+    pub fn wiki_pages(&self, target: &str) -> Vec<String> {
+        let name = wiki_name(target);
+        self.paths
+            .get(&name)
+            .or_else(|| self.file_names.get(&name))
+            .or_else(|| self.headings.get(&name))
+            .map(|x| x.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Links as seen from the page with the given link path
+    pub fn page<'a>(&'a self, link_path: &'a str) -> PageLinks<'a> {
+        PageLinks {
+            site: self,
+            link_path,
+        }
+    }
+}
+
+/// Resolves the links found within a single page of the site.
+#[derive(Debug)]
+pub struct PageLinks<'a> {
+    site: &'a SiteLinks,
+    link_path: &'a str,
+}
+
+impl<'a> PageLinks<'a> {
+    /// Link paths of the pages a wiki link on this page could refer to
+    pub fn wiki_pages(&self, target: &str) -> Vec<String> {
+        self.site.wiki_pages(target)
+    }
+
+    /// Pages of the site which link to this page
+    pub fn backlinks(&self) -> &'a [Backlink] {
+        self.site.backlinks(self.link_path)
+    }
+
+    /// Relative link from this page to the page with the given link path
+    pub fn href_to(&self, link_path: &str) -> String {
+        let site_root = "../".repeat(self.link_path.matches('/').count());
+        format!("{}{}{}", site_root, link_path, HTML_EXTENSION)
+    }
+
+    /// Rewrite a relative link to a Markdown file so that it points to the
+    /// HTML page generated for it, keeping any query or fragment. Links which
+    /// are absolute, external or not to a Markdown file are returned as is. A
+    /// warning is logged when the linked file is not part of the site.
+    pub fn rewrite(&self, href: &str) -> String {
+        let (path, suffix) = match markdown_path(href) {
+            Some(parts) => parts,
+            None => return href.to_string(),
+        };
+        if self.target(path).is_none() {
+            warn!(
+                "Link to {} from {}.md does not match a Markdown file of the site",
+                href, self.link_path
+            );
+        }
+        format!("{}{}{}", path, HTML_EXTENSION, suffix)
+    }
+
+    /// Targets of the links to Markdown files and of the wiki links within the
+    /// content of this page, along with the link paths of the pages each
+    /// resolves to. The page depends upon these, as it must be rendered again
+    /// once a target appears, disappears or resolves to another page.
+    pub fn targets(&self, content: &str) -> BTreeMap<String, Vec<String>> {
+        let mut targets = BTreeMap::new();
+        let options = OPTION_ENABLE_TABLES | OPTION_ENABLE_FOOTNOTES;
+        for event in Parser::new_ext(content, options) {
+            if let Event::Start(Tag::Link(href, _)) = event {
+                if let Some((path, _)) = markdown_path(&href) {
+                    targets.insert(path.to_string(), self.target(path).into_iter().collect());
+                }
+            }
+        }
+        for target in wiki::targets(content) {
+            let pages = self.wiki_pages(&target);
+            targets.insert(format!("[[{}]]", target), pages);
+        }
+        targets
+    }
+
+    /// Link paths of the other pages of the site which the Markdown content
+    /// of this page links to, through either Markdown or wiki links.
+    fn linked_pages(&self, content: &str) -> BTreeSet<String> {
+        let mut linked: BTreeSet<String> = self
+            .targets(content)
+            .into_values()
+            .filter_map(|pages| pages.into_iter().next())
+            .collect();
+        linked.remove(self.link_path);
+        linked
+    }
+
+    /// Link path of the page of the site a link to the Markdown file at
+    /// `path`, without its extension, points to.
+    fn target(&self, path: &str) -> Option<String> {
+        self.resolve(path)
+            .filter(|x| self.site.pages.contains(x))
+    }
+
+    /// Resolve the path of a link relative to this page into the link path
+    /// of the target. Returns `None` when the path leaves the site.
+    fn resolve(&self, path: &str) -> Option<String> {
+        let mut components: Vec<&str> = self.link_path.split('/').collect();
+        // Links are relative to the directory holding the page
+        components.pop();
+        for component in path.split('/') {
+            match component {
+                "" | "." => (),
+                ".." => {
+                    components.pop()?;
+                }
+                _ => components.push(component),
+            }
+        }
+        Some(components.join("/"))
+    }
+}
+
+/// Split a relative link to a Markdown file into its path, without the
+/// extension, and any query or fragment. Returns `None` for any other link.
+fn markdown_path(href: &str) -> Option<(&str, &str)> {
+    if is_absolute(href) {
+        return None;
+    }
+    let split = href.find(&['?', '#'][..]).unwrap_or(href.len());
+    let (path, suffix) = href.split_at(split);
+    if !path.to_lowercase().ends_with(MARKDOWN_EXTENSION) {
+        return None;
+    }
+    Some((&path[..path.len() - MARKDOWN_EXTENSION.len()], suffix))
+}
+
+/// Name of a page as written in a wiki link, ignoring case and whether words
+/// are separated by spaces, hyphens or underscores.
+fn wiki_name(name: &str) -> String {
+    name.trim()
+        .to_lowercase()
+        .chars()
+        .map(|x| if x == ' ' || x == '_' { '-' } else { x })
+        .collect()
+}
+
+/// Whether the link is external, absolute or only a fragment within the page.
+fn is_absolute(href: &str) -> bool {
+    if href.starts_with('/') || href.starts_with('#') {
+        return true;
+    }
+    // Anything with a scheme such as `https:` or `mailto:`
+    match href.find(':') {
+        Some(colon) => !href[..colon].contains(&['/', '?', '#'][..]),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use file_utils;
+    use test_utils;
+    use walker::{MarkdownFile, MarkdownFileList};
+
+    fn site() -> super::SiteLinks {
+        let root = Path::new("tests/resources/input/site");
+        super::SiteLinks::new(&MarkdownFileList::new(vec![
+            MarkdownFile::from_root(root, &root.join("second-page.md")),
+            MarkdownFile::from_root(root, &root.join("nested/nested-page.md")),
+        ]))
+    }
+
+    #[test]
+    fn test_rewrite_relative() {
+        let site = site();
+        let links = site.page("second-page");
+        assert_eq!(links.rewrite("nested/nested-page.md"), "nested/nested-page.html");
+        assert_eq!(links.rewrite("./second-page.md#usage"), "./second-page.html#usage");
+        let links = site.page("nested/nested-page");
+        assert_eq!(links.rewrite("../second-page.md?q=1#a"), "../second-page.html?q=1#a");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let site = site();
+        let links = site.page("nested/nested-page");
+        assert_eq!(links.resolve("../second-page"), Some("second-page".to_string()));
+        assert_eq!(links.resolve("./other"), Some("nested/other".to_string()));
+        assert_eq!(links.resolve("../../outside"), None);
+    }
+
+    #[test]
+    fn test_targets() {
+        let site = site();
+        let links = site.page("second-page");
+        let content = "[Nested](nested/nested-page.md#a), [missing](missing.md) \
+                       and [external](https://example.com/page.md)";
+        let targets = links.targets(content);
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets["nested/nested-page"], vec!["nested/nested-page"]);
+        assert!(targets["missing"].is_empty());
+
+        let targets = links.targets("[[Nested Page]] and [[Third Page|third]]");
+        assert_eq!(targets["[[Nested Page]]"], vec!["nested/nested-page"]);
+        assert!(targets["[[Third Page]]"].is_empty());
+    }
+
+    #[test]
+    fn test_wiki_pages() {
+        let site = site();
+        assert_eq!(site.wiki_pages("Second Page"), vec!["second-page"]);
+        assert_eq!(site.wiki_pages("nested_page"), vec!["nested/nested-page"]);
+        assert_eq!(site.wiki_pages("Nested/Nested Page"), vec!["nested/nested-page"]);
+        assert!(site.wiki_pages("Third Page").is_empty());
+        let links = site.page("nested/nested-page");
+        assert_eq!(links.href_to("second-page"), "../second-page.html");
+    }
+
+    #[test]
+    fn test_ambiguous_paths() {
+        let root = test_utils::temp_dir("wiki-paths-test");
+        for name in &["team notes.md", "team-notes.md"] {
+            file_utils::write_to_file(root.join(name), "# Notes".to_string()).unwrap();
+        }
+        let site = super::SiteLinks::new(&MarkdownFileList::new(vec![
+            MarkdownFile::from_root(&root, &root.join("team-notes.md")),
+            MarkdownFile::from_root(&root, &root.join("team notes.md")),
+        ]));
+        assert_eq!(site.wiki_pages("Team Notes"), vec!["team notes", "team-notes"]);
+    }
+
+    #[test]
+    fn test_backlinks() {
+        let root = Path::new("tests/resources/input/wiki");
+        let site = super::SiteLinks::new(&MarkdownFileList::new(vec![
+            MarkdownFile::from_root(root, &root.join("guide.md")),
+            MarkdownFile::from_root(root, &root.join("notes.md")),
+            MarkdownFile::from_root(root, &root.join("archive/notes.md")),
+        ]));
+        let backlinks = site.backlinks("guide");
+        assert_eq!(backlinks.len(), 2);
+        assert_eq!(backlinks[0].file_path, "notes");
+        assert_eq!(backlinks[0].header, "Team Notes");
+        assert_eq!(backlinks[1].file_path, "archive/notes");
+        // Links to itself and from code are not backlinks
+        assert!(site.backlinks("notes").is_empty());
+        assert_eq!(site.page("archive/notes").backlinks().len(), 1);
+    }
+
+    #[test]
+    fn test_untouched_links() {
+        let site = site();
+        let links = site.page("second-page");
+        for href in &[
+            "https://example.com/page.md",
+            "mailto:someone@example.com",
+            "/absolute/page.md",
+            "#heading",
+            "image.png",
+            "notes.mdx",
+        ] {
+            assert_eq!(&links.rewrite(href), href);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use serde_json;
+
+use file_utils;
+use Result;
+
+/// Name of the manifest file within the output directory.
+pub const MANIFEST_NAME: &str = ".made-up-manifest.json";
+
+/// Record of the previous build of the site kept in the output directory, so
+/// that the next build can skip the pages whose inputs have not changed and
+/// leave the outputs whose content is the same untouched.
+///
+/// The hashes are only compared against those produced by the same build of
+/// Made-Up, any difference simply causes the affected files to be rebuilt.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct Manifest {
+    // Hash of the configuration and the templates every page is rendered with
+    settings: u64,
+    // Source hash of each page, by the path of its output
+    pages: BTreeMap<String, u64>,
+    // Hash of the targets of the links within each page, by its output
+    links: BTreeMap<String, u64>,
+    // Hash of the pages linking to each page which has any, by its output
+    backlinks: BTreeMap<String, u64>,
+    // Hash of the headings of every page, which the index depends upon
+    index: u64,
+    // Content hash of every file written, by its path within the output
+    outputs: BTreeMap<String, u64>,
+    // Outputs of the pages of the previous build which are no longer part of
+    // the site, deleted once the new build is written
+    #[serde(skip)]
+    removed: Vec<String>,
+}
+
+impl Manifest {
+    /// Read the manifest from the output directory, returning an empty
+    /// manifest when there is none or it can not be read.
+    pub fn load<P: AsRef<Path>>(out_dir: P) -> Manifest {
+        file_utils::read_from_file(out_dir.as_ref().join(MANIFEST_NAME))
+            .ok()
+            .and_then(|x| serde_json::from_str(&x).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the manifest to the output directory
+    pub fn save<P: AsRef<Path>>(&self, out_dir: P) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)
+            .expect("Failed to serialize the build manifest");
+        file_utils::write_to_file(out_dir.as_ref().join(MANIFEST_NAME), content)
+    }
+
+    /// Start the manifest for a new build from this previous one. The outputs
+    /// are kept as the files skipped by the new build remain as they were.
+    pub fn next_build(&self, settings: u64) -> Manifest {
+        Manifest {
+            settings,
+            pages: BTreeMap::new(),
+            links: BTreeMap::new(),
+            backlinks: BTreeMap::new(),
+            index: 0,
+            outputs: self.outputs.clone(),
+            removed: vec![],
+        }
+    }
+
+    /// Record the pages of the `previous` build missing from this one, such as
+    /// those whose source was removed, so that their outputs are deleted.
+    pub fn remove_missing(&mut self, previous: &Manifest) {
+        self.removed = previous
+            .pages
+            .keys()
+            .filter(|x| !self.pages.contains_key(*x))
+            .cloned()
+            .collect();
+    }
+
+    /// Delete the outputs of the pages removed since the previous build
+    pub fn prune<P: AsRef<Path>>(&mut self, out_dir: P) -> Result<()> {
+        for output in mem::take(&mut self.removed) {
+            let path = out_dir.as_ref().join(&output);
+            if file_utils::check_file_exists(&path) {
+                debug!("Removing {}", path.display());
+                fs::remove_file(&path)?;
+            }
+            self.outputs.remove(&output);
+        }
+        Ok(())
+    }
+
+    /// Whether the page needs to be rendered again, recording its source hash
+    /// within `next`.
+    pub fn page_changed(&self, next: &mut Manifest, output: &str, source: u64) -> bool {
+        next.pages.insert(output.to_string(), source);
+        self.settings != next.settings || self.pages.get(output) != Some(&source)
+    }
+
+    /// Whether the targets the links of the page resolve to differ from the
+    /// previous build, recording their hash within `next`. A page missing
+    /// from the previous build is left to `page_changed`.
+    pub fn links_changed(&self, next: &mut Manifest, output: &str, targets: u64) -> bool {
+        next.links.insert(output.to_string(), targets);
+        match self.links.get(output) {
+            Some(previous) => *previous != targets,
+            None => false,
+        }
+    }
+
+    /// Whether the pages linking to the page differ from the previous build,
+    /// recording them within `next`. Unlike its source this is regardless of
+    /// the settings, so the page is also rendered when only the pages which
+    /// link to it were.
+    pub fn backlinks_changed<T: Hash>(
+        &self,
+        next: &mut Manifest,
+        output: &str,
+        backlinks: &[T],
+    ) -> bool {
+        if !backlinks.is_empty() {
+            next.backlinks.insert(output.to_string(), hash(&backlinks));
+        }
+        self.backlinks.get(output) != next.backlinks.get(output)
+    }
+
+    /// Whether the index needs to be rendered again, recording the hash of the
+    /// headings within `next`.
+    pub fn index_changed(&self, next: &mut Manifest, headings: u64) -> bool {
+        next.index = headings;
+        self.settings != next.settings || self.index != headings
+    }
+
+    /// Whether the output would change by writing `content`, recording the
+    /// content hash.
+    pub fn output_changed(&mut self, output: &str, content: u64) -> bool {
+        self.outputs.insert(output.to_string(), content) != Some(content)
+    }
+}
+
+/// Hash of the value used to detect changes between builds.
+pub fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = FnvHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// 64 bit FNV-1a, used rather than the hasher of the standard library as the
+// algorithm of the latter may change between Rust releases, which would turn
+// every manifest stale.
+struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> FnvHasher {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_save_and_load() {
+        let out_dir = env::temp_dir().join("made-up-manifest-test");
+        let _ = fs::remove_dir_all(&out_dir);
+        assert_eq!(super::Manifest::load(&out_dir), super::Manifest::default());
+
+        fs::create_dir_all(&out_dir).unwrap();
+        let mut manifest = super::Manifest::default().next_build(1);
+        manifest.output_changed("index.html", 2);
+        manifest.save(&out_dir).unwrap();
+        assert_eq!(super::Manifest::load(&out_dir), manifest);
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut previous = super::Manifest::default().next_build(1);
+        assert!(super::Manifest::default().page_changed(&mut previous, "page.html", 10));
+        assert!(super::Manifest::default().index_changed(&mut previous, 20));
+        assert!(!super::Manifest::default().links_changed(&mut previous, "page.html", 30));
+
+        let mut next = previous.next_build(1);
+        assert!(!previous.page_changed(&mut next, "page.html", 10));
+        assert!(previous.page_changed(&mut next, "other.html", 10));
+        assert!(!previous.index_changed(&mut next, 20));
+        assert!(!previous.backlinks_changed(&mut next, "page.html", &[] as &[&str]));
+        assert!(previous.backlinks_changed(&mut next, "page.html", &["other"]));
+
+        assert!(!previous.links_changed(&mut next, "page.html", 30));
+        assert!(previous.links_changed(&mut next, "page.html", 40));
+
+        // Changing the settings requires everything to be rendered again
+        let mut next = previous.next_build(2);
+        assert!(previous.page_changed(&mut next, "page.html", 10));
+        assert!(previous.index_changed(&mut next, 20));
+    }
+
+    #[test]
+    fn test_hasher() {
+        use std::hash::Hasher;
+
+        // Reference values of FNV-1a, which must hold on every Rust release
+        let hasher = super::FnvHasher::default();
+        assert_eq!(hasher.finish(), 0xcbf2_9ce4_8422_2325);
+        let mut hasher = super::FnvHasher::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+        let mut hasher = super::FnvHasher::default();
+        hasher.write(b"foobar");
+        assert_eq!(hasher.finish(), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn test_remove_missing() {
+        let mut previous = super::Manifest::default().next_build(1);
+        super::Manifest::default().page_changed(&mut previous, "page.html", 10);
+        super::Manifest::default().page_changed(&mut previous, "other.html", 10);
+        previous.output_changed("page.html", 20);
+        previous.output_changed("other.html", 20);
+
+        let mut next = previous.next_build(1);
+        previous.page_changed(&mut next, "page.html", 10);
+        next.remove_missing(&previous);
+        assert_eq!(next.removed, vec!["other.html".to_string()]);
+
+        let out_dir = env::temp_dir().join("made-up-prune-test");
+        let _ = fs::remove_dir_all(&out_dir);
+        next.prune(&out_dir).unwrap();
+        assert!(next.removed.is_empty());
+        assert!(next.outputs.contains_key("page.html"));
+        assert!(!next.outputs.contains_key("other.html"));
+    }
+
+    #[test]
+    fn test_output_changed() {
+        let mut manifest = super::Manifest::default();
+        assert!(manifest.output_changed("page.html", 1));
+        assert!(!manifest.output_changed("page.html", 1));
+        assert!(manifest.output_changed("page.html", 2));
+    }
+}
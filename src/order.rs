@@ -0,0 +1,277 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde_yaml;
+
+use config::Configuration;
+use file_utils;
+use walker::MarkdownFile;
+use {ErrorKind, Result};
+
+/// Name of the file within a directory listing the order of its pages and
+/// directories.
+pub const ORDER_FILE: &str = "_order.yml";
+/// Extension which may be given on the pages listed in an order.
+const MARKDOWN_EXTENSION: &str = ".md";
+
+/// What the pages within each directory are sorted by.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SortBy {
+    /// Name of the file
+    #[default]
+    Name,
+    /// `weight` in the front matter, lightest first
+    Weight,
+    /// `date` in the front matter, newest first
+    Date,
+    /// Heading of the page
+    Title,
+}
+
+impl SortBy {
+    /// What to sort by as given in the configuration. Returns an error if it
+    /// is not known.
+    pub fn from_config(config: &Configuration) -> Result<SortBy> {
+        match config.sort_by().as_str() {
+            "name" => Ok(SortBy::Name),
+            "weight" => Ok(SortBy::Weight),
+            "date" => Ok(SortBy::Date),
+            "title" => Ok(SortBy::Title),
+            sort_by => Err(ErrorKind::Fail(format!(
+                "Unknown sort_by {}, expected name, weight, date or title",
+                sort_by
+            )).into()),
+        }
+    }
+}
+
+/// Order of the pages and directories within each directory of the site. Any
+/// listed explicitly come first, in the order listed, followed by the rest
+/// sorted by `sort_by` and then by name.
+#[derive(Debug, Default)]
+pub struct PageOrder {
+    sort_by: SortBy,
+    // Names of the pages and directories listed within each directory, by the
+    // path of the directory relative to the root
+    explicit: HashMap<PathBuf, Vec<String>>,
+}
+
+/// Key the pages and directories within a directory are sorted by.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct SortKey {
+    // Position within the explicit order, those not listed being last
+    position: usize,
+    // Directories not listed follow the pages not listed
+    directory: bool,
+    // Pages without a value to sort by follow those with one
+    missing: bool,
+    value: SortValue,
+    name: String,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue {
+    Name,
+    Weight(i64),
+    Date(Reverse<String>),
+    Title(String),
+}
+
+impl PageOrder {
+    /// Read the order of the pages from the configuration and the
+    /// `_order.yml` of each directory holding the files. The `order` of the
+    /// configuration lists pages and directories by their path from the root,
+    /// while an `_order.yml` lists them by name and replaces the entries of
+    /// the configuration for its directory. Returns an error if an
+    /// `_order.yml` is not a list of names.
+    pub fn load(
+        root_dir: &Path,
+        config: &Configuration,
+        files: &[MarkdownFile],
+    ) -> Result<PageOrder> {
+        let mut explicit: HashMap<PathBuf, Vec<String>> = HashMap::new();
+        for entry in config.order() {
+            let entry = Path::new(entry.trim_matches('/'));
+            if let Some(name) = entry.file_name().and_then(|x| x.to_str()) {
+                let dir = entry.parent().unwrap_or_else(|| Path::new(""));
+                explicit
+                    .entry(dir.to_path_buf())
+                    .or_default()
+                    .push(name.to_string());
+            }
+        }
+
+        let mut dirs: Vec<PathBuf> = files
+            .iter()
+            .flat_map(|x| x.get_relative_path().ancestors().skip(1))
+            .map(Path::to_path_buf)
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            let path = root_dir.join(&dir).join(ORDER_FILE);
+            if !file_utils::check_file_exists(&path) {
+                continue;
+            }
+            let names: Vec<String> = serde_yaml::from_str(&file_utils::read_from_file(&path)?)
+                .map_err(|err| {
+                    ErrorKind::Fail(format!(
+                        "Invalid order in {}, expected a list of names: {}",
+                        path.display(),
+                        err
+                    ))
+                })?;
+            explicit.insert(dir, names);
+        }
+
+        for names in explicit.values_mut() {
+            for name in names.iter_mut() {
+                if name.to_lowercase().ends_with(MARKDOWN_EXTENSION) {
+                    let length = name.len() - MARKDOWN_EXTENSION.len();
+                    name.truncate(length);
+                }
+            }
+        }
+        Ok(PageOrder {
+            sort_by: SortBy::from_config(config)?,
+            explicit,
+        })
+    }
+
+    /// Key to sort the directory of the name within the directory at `dir`
+    /// by, along with the files and other directories within it
+    pub fn directory_key(&self, dir: &Path, name: &str) -> SortKey {
+        SortKey {
+            position: self.position(dir, name),
+            directory: true,
+            missing: false,
+            value: SortValue::Name,
+            name: name.to_string(),
+        }
+    }
+
+    /// Key to sort the file within the directory at `dir` by, along with the
+    /// other files and directories within it
+    pub fn file_key(&self, dir: &Path, file: &MarkdownFile) -> SortKey {
+        let name = file.get_file_name();
+        let front_matter = file.get_front_matter().unwrap_or_default();
+        let value = match self.sort_by {
+            SortBy::Name => Some(SortValue::Name),
+            SortBy::Weight => front_matter.weight().map(SortValue::Weight),
+            SortBy::Date => front_matter
+                .date()
+                .map(|x| SortValue::Date(Reverse(x.to_owned()))),
+            SortBy::Title => Some(SortValue::Title(file.get_heading().to_lowercase())),
+        };
+        SortKey {
+            position: self.position(dir, &name),
+            directory: false,
+            missing: value.is_none(),
+            value: value.unwrap_or(SortValue::Name),
+            name,
+        }
+    }
+
+    /// Position of the name within the explicit order of the directory
+    fn position(&self, dir: &Path, name: &str) -> usize {
+        self.explicit
+            .get(dir)
+            .and_then(|x| x.iter().position(|x| x == name))
+            .unwrap_or(usize::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use config::Configuration;
+    use walker::{Entry, MarkdownFile, MarkdownFileList};
+    use super::{PageOrder, SortBy};
+
+    const ROOT: &str = "tests/resources/input/order";
+
+    fn files() -> Vec<MarkdownFile> {
+        let root = Path::new(ROOT);
+        ["zebra.md", "advanced.md", "getting-started.md", "guide/intro.md", "guide/setup.md"]
+            .iter()
+            .map(|x| MarkdownFile::from_root(root, &root.join(x)))
+            .collect()
+    }
+
+    /// Link paths of the files in the order given
+    fn sorted(order: &PageOrder) -> Vec<String> {
+        MarkdownFileList::new(files())
+            .sorted(order)
+            .get_files()
+            .iter()
+            .map(|x| x.get_link_path())
+            .collect()
+    }
+
+    fn sort_by(sort_by: SortBy) -> Vec<String> {
+        sorted(&PageOrder {
+            sort_by,
+            explicit: HashMap::new(),
+        })
+    }
+
+    #[test]
+    fn test_sort_by() {
+        let guide = ["guide/intro", "guide/setup"];
+        assert_eq!(
+            sort_by(SortBy::Name)[..3],
+            ["advanced", "getting-started", "zebra"]
+        );
+        assert_eq!(sort_by(SortBy::Name)[3..], guide);
+        // Pages without a weight or date come last
+        assert_eq!(
+            sort_by(SortBy::Weight)[..3],
+            ["getting-started", "advanced", "zebra"]
+        );
+        assert_eq!(
+            sort_by(SortBy::Date)[..3],
+            ["advanced", "getting-started", "zebra"]
+        );
+        assert_eq!(
+            sort_by(SortBy::Title)[..3],
+            ["zebra", "advanced", "getting-started"]
+        );
+    }
+
+    #[test]
+    fn test_explicit_order() {
+        let mut explicit = HashMap::new();
+        explicit.insert(PathBuf::new(), vec!["guide".to_string(), "zebra".to_string()]);
+        let order = PageOrder {
+            sort_by: SortBy::Name,
+            explicit,
+        };
+        // The guide is listed first, before the pages of the root
+        assert_eq!(
+            sorted(&order),
+            vec!["guide/intro", "guide/setup", "zebra", "advanced", "getting-started"]
+        );
+        let list = MarkdownFileList::new(files()).sorted(&order);
+        let root = list.get_root();
+        assert_eq!(root.get_sections()[0].get_name(), "guide");
+        assert_eq!(
+            root.get_entries()[..3],
+            [Entry::Section(0), Entry::Page(2), Entry::Page(3)]
+        );
+        assert_eq!(list.get_section_files(root)[0].get_link_path(), "zebra");
+    }
+
+    #[test]
+    fn test_load() {
+        let config = Configuration::from(Path::new(ROOT).join("mdup.yml")).unwrap();
+        let order = PageOrder::load(Path::new(ROOT), &config, &files()).unwrap();
+        assert_eq!(order.sort_by, SortBy::Weight);
+        // The order of the guide is replaced by its _order.yml
+        assert_eq!(
+            sorted(&order),
+            vec!["zebra", "getting-started", "advanced", "guide/setup", "guide/intro"]
+        );
+    }
+}
@@ -0,0 +1,403 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json;
+
+use file_utils;
+use Result;
+
+/// Page served when the site does not provide its own `404.html`.
+const NOT_FOUND_PAGE: &str = "<!DOCTYPE html>\n<html><head><title>404 Not Found</title></head>\
+                              <body><h1>404 Not Found</h1></body></html>\n";
+/// File served for requests to a directory.
+const DIRECTORY_INDEX: &str = "index.html";
+/// Path of the script injected into pages when live reload is enabled.
+const LIVE_RELOAD_SCRIPT: &str = "/__made-up/live-reload.js";
+/// Path of the event stream the live reload script listens to.
+const LIVE_RELOAD_EVENTS: &str = "/__made-up/events";
+/// How often an idle event stream is written to, to find closed connections.
+const KEEP_ALIVE_SECS: u64 = 15;
+
+/// Simple HTTP server for previewing a generated site locally. Files are
+/// served from the output directory in the same way a typical web server
+/// would, so that relative links and `index.html` pages resolve as they will
+/// in production.
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    site_dir: PathBuf,
+    live_reload: Option<Arc<LiveReload>>,
+}
+
+impl Server {
+    /// Bind the server to the address provided, such as `127.0.0.1:8000`, to
+    /// serve the files within `site_dir`.
+    pub fn bind<P: AsRef<Path>>(site_dir: P, address: &str) -> Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(address)?,
+            site_dir: site_dir.as_ref().to_path_buf(),
+            live_reload: None,
+        })
+    }
+
+    /// Push the rebuilds signalled through `live_reload` to the open pages
+    pub fn set_live_reload(&mut self, live_reload: Arc<LiveReload>) {
+        self.live_reload = Some(live_reload);
+    }
+
+    /// Returns the address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve requests until the process is stopped. Each connection is
+    /// handled on its own thread.
+    pub fn run(self) -> Result<()> {
+        info!("Serving {} at http://{}/", self.site_dir.display(), self.local_addr()?);
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            let site_dir = self.site_dir.clone();
+            let live_reload = self.live_reload.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &site_dir, live_reload) {
+                    warn!("Failed to respond to request: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of the latest build of the site.
+#[derive(Debug, Default, Clone)]
+struct BuildState {
+    version: u64,
+    error: Option<String>,
+}
+
+/// Notifies the pages open in a browser when the site has been rebuilt, so
+/// they reload, or has failed to build, so they show the error.
+#[derive(Debug, Default)]
+pub struct LiveReload {
+    state: Mutex<BuildState>,
+    changed: Condvar,
+}
+
+impl LiveReload {
+    pub fn new() -> LiveReload {
+        LiveReload::default()
+    }
+
+    /// The site has been rebuilt and the open pages should reload
+    pub fn reload(&self) {
+        self.update(None);
+    }
+
+    /// The site failed to build, show the error on the open pages
+    pub fn build_failed(&self, message: String) {
+        self.update(Some(message));
+    }
+
+    fn update(&self, error: Option<String>) {
+        let mut state = self.state.lock().expect("Live reload state poisoned");
+        state.version += 1;
+        state.error = error;
+        self.changed.notify_all();
+    }
+
+    fn current(&self) -> BuildState {
+        self.state.lock().expect("Live reload state poisoned").clone()
+    }
+
+    /// Wait for a build after `version`, returning `None` if there was not one
+    /// before the timeout.
+    fn wait_for_build(&self, version: u64, timeout: Duration) -> Option<BuildState> {
+        let mut state = self.state.lock().expect("Live reload state poisoned");
+        if state.version == version {
+            state = self.changed
+                .wait_timeout(state, timeout)
+                .expect("Live reload state poisoned")
+                .0;
+        }
+        if state.version == version {
+            None
+        } else {
+            Some(state.clone())
+        }
+    }
+}
+
+/// Server-sent event telling the live reload script about a build.
+fn build_event(state: &BuildState) -> String {
+    match state.error {
+        Some(ref error) => format!(
+            "event: build-error\ndata: {}\n\n",
+            serde_json::to_string(error).expect("Failed to serialize build error")
+        ),
+        None => "event: reload\ndata: {}\n\n".to_string(),
+    }
+}
+
+/// Keep the connection open, writing an event for each build of the site
+/// until the browser goes away.
+fn stream_events(mut stream: TcpStream, live_reload: &LiveReload) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+         Cache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+    )?;
+    let mut state = live_reload.current();
+    // A page loaded while the build is broken should still show the error
+    if state.error.is_some() {
+        stream.write_all(build_event(&state).as_bytes())?;
+    }
+    loop {
+        match live_reload.wait_for_build(state.version, Duration::from_secs(KEEP_ALIVE_SECS)) {
+            Some(next) => {
+                stream.write_all(build_event(&next).as_bytes())?;
+                state = next;
+            }
+            None => stream.write_all(b": keep-alive\n\n")?,
+        }
+        stream.flush()?;
+    }
+}
+
+/// Response to a single request.
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &str, body: Vec<u8>) -> Response {
+        Response {
+            status,
+            headers: vec![("Content-Type", content_type.to_string())],
+            body,
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W, include_body: bool) -> Result<()> {
+        write!(writer, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        for (name, value) in &self.headers {
+            write!(writer, "{}: {}\r\n", name, value)?;
+        }
+        write!(writer, "Content-Length: {}\r\n", self.body.len())?;
+        write!(writer, "Connection: close\r\n\r\n")?;
+        if include_body {
+            writer.write_all(&self.body)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Read the request from the stream and write back the response.
+fn handle_connection(
+    stream: TcpStream,
+    site_dir: &Path,
+    live_reload: Option<Arc<LiveReload>>,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip over the headers, none of them are needed
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim() != "" {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+    let response = match (method, live_reload) {
+        ("GET", Some(ref live_reload)) if target == LIVE_RELOAD_EVENTS => {
+            return stream_events(stream, live_reload);
+        }
+        ("GET", Some(_)) | ("HEAD", Some(_)) if target == LIVE_RELOAD_SCRIPT => Response::new(
+            200,
+            "application/javascript; charset=utf-8",
+            include_bytes!("../theme/live-reload.js").to_vec(),
+        ),
+        ("GET", _) | ("HEAD", _) => respond(site_dir, target),
+        _ => Response::new(405, "text/plain", b"Method Not Allowed".to_vec()),
+    };
+    debug!("{} {} -> {}", method, target, response.status);
+    let mut stream = stream;
+    response.write_to(&mut stream, method != "HEAD")
+}
+
+/// Build the response for the request target within the site directory.
+fn respond(site_dir: &Path, target: &str) -> Response {
+    let path = target.split(&['?', '#'][..]).next().unwrap_or("");
+    let relative = match request_path(path) {
+        Some(relative) => relative,
+        None => return not_found(site_dir),
+    };
+    let mut file_path = site_dir.join(relative);
+    if file_utils::check_dir_exists(&file_path) {
+        if !path.ends_with('/') {
+            // Redirect so relative links within the index resolve correctly
+            let mut response = Response::new(301, "text/plain", vec![]);
+            response.headers.push(("Location", format!("{}/", path)));
+            return response;
+        }
+        file_path = file_path.join(DIRECTORY_INDEX);
+    }
+    match fs::read(&file_path) {
+        Ok(body) => Response::new(200, mime_type(&file_path), body),
+        Err(_) => not_found(site_dir),
+    }
+}
+
+/// The 404 response, using the `404.html` of the site when it has one.
+fn not_found(site_dir: &Path) -> Response {
+    let body = fs::read(site_dir.join("404.html")).unwrap_or_else(|_| NOT_FOUND_PAGE.into());
+    Response::new(404, "text/html; charset=utf-8", body)
+}
+
+/// Convert the path of a request into a path relative to the site directory.
+/// Returns `None` when the path would leave the site directory.
+fn request_path(path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(path)?;
+    let mut relative = PathBuf::new();
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    Some(relative)
+}
+
+/// Decode the `%XX` escapes within the path of a request.
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = path.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// MIME type of the file based on its extension.
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path.extension()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "application/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "xml" => "application/xml",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Reason phrase for the status codes used by the server.
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        301 => "Moved Permanently",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+
+    const SITE: &str = "tests/resources/output";
+
+    #[test]
+    fn test_respond() {
+        let site = Path::new(SITE);
+        let response = super::respond(site, "/index_good.html?query#fragment");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers[0].1, "text/html; charset=utf-8");
+        assert_eq!(response.body, ::std::fs::read("tests/resources/output/index_good.html").unwrap());
+        assert_eq!(super::respond(site, "/missing.html").status, 404);
+        assert_eq!(super::respond(site, "/../output/index_good.html").status, 404);
+    }
+
+    #[test]
+    fn test_directory_redirect() {
+        let response = super::respond(Path::new("tests/resources"), "/output");
+        assert_eq!(response.status, 301);
+        assert_eq!(response.headers[1], ("Location", "/output/".to_string()));
+    }
+
+    #[test]
+    fn test_request_path() {
+        assert_eq!(
+            super::request_path("/nested/my%20page.html"),
+            Some(PathBuf::from("nested/my page.html"))
+        );
+        assert_eq!(super::request_path("/"), Some(PathBuf::new()));
+        assert_eq!(super::request_path("/a/../../secret"), None);
+        assert_eq!(super::request_path("/bad%zz"), None);
+    }
+
+    #[test]
+    fn test_mime_type() {
+        assert_eq!(super::mime_type(Path::new("style.CSS")), "text/css; charset=utf-8");
+        assert_eq!(super::mime_type(Path::new("images/logo.png")), "image/png");
+        assert_eq!(super::mime_type(Path::new("binary")), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_serve() {
+        let server = super::Server::bind(SITE, "127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /missing.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("<h1>404 Not Found</h1>"));
+    }
+}
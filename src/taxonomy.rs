@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use walker::MarkdownFileList;
+use templates::Element;
+use Result;
+
+/// Name of the page listing every term of a taxonomy, within its directory.
+const INDEX_NAME: &str = "index.html";
+/// Slug of the term which would be written over the page listing every term,
+/// and the slug it is given instead, which no other term can have.
+const INDEX_SLUG: &str = "index";
+const INDEX_TERM_SLUG: &str = "_index";
+
+/// Pages of the site grouped by the terms they are given for a taxonomy in
+/// their front matter, such as their `tags`. The pages for a taxonomy are
+/// written within the directory of its name.
+#[derive(Debug, Hash)]
+pub struct Taxonomy {
+    name: String,
+    terms: Vec<Term>,
+}
+
+/// Term of a taxonomy along with the pages given it.
+#[derive(Serialize, Debug, Hash)]
+pub struct Term {
+    name: String,
+    slug: String,
+    pages: Vec<Element>,
+    // Number of pages, for the templates
+    count: usize,
+}
+
+impl Taxonomy {
+    /// Group the files by their terms for the taxonomy. Terms with the same
+    /// slug are the same term, named as it was first written. The terms are
+    /// in order of their slug and the pages of each are in the order of the
+    /// files. A term with the slug `index` is linked by `_index` instead, so
+    /// that its page is kept apart from the list of terms.
+    pub fn collect(name: &str, files: &MarkdownFileList) -> Result<Taxonomy> {
+        let mut terms: BTreeMap<String, Term> = BTreeMap::new();
+        for file in files.get_files() {
+            for term in file.get_front_matter()?.terms(name) {
+                let slug = match slugify(&term) {
+                    ref slug if slug == INDEX_SLUG => INDEX_TERM_SLUG.to_string(),
+                    slug => slug,
+                };
+                if slug.is_empty() {
+                    warn!(
+                        "Ignoring the {} {:?} of {}, it has nothing to link to it by",
+                        name,
+                        term,
+                        file.get_path().display()
+                    );
+                    continue;
+                }
+                let entry = terms.entry(slug.clone()).or_insert_with(|| Term {
+                    name: term.trim().to_string(),
+                    slug,
+                    pages: vec![],
+                    count: 0,
+                });
+                let element = Element::from(file);
+                if !entry.pages.contains(&element) {
+                    entry.pages.push(element);
+                    entry.count += 1;
+                }
+            }
+        }
+        Ok(Taxonomy {
+            name: name.to_string(),
+            terms: terms.into_values().collect(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn terms(&self) -> &[Term] {
+        &self.terms
+    }
+
+    /// Path of the page listing every term, relative to the output directory
+    pub fn index_path(&self) -> PathBuf {
+        PathBuf::from(&self.name).join(INDEX_NAME)
+    }
+
+    /// Path of the page listing the pages given the term, relative to the
+    /// output directory
+    pub fn term_path(&self, term: &Term) -> PathBuf {
+        PathBuf::from(&self.name).join(format!("{}.html", term.slug))
+    }
+}
+
+impl Term {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    pub fn pages(&self) -> &[Element] {
+        &self.pages
+    }
+}
+
+/// Slug a term is linked by, so that its page keeps the same URL however the
+/// term is written. Letters are lowercased and any run of other characters
+/// becomes a single hyphen, i.e. `Rust Tips!` is `rust-tips`.
+pub fn slugify(term: &str) -> String {
+    let mut slug = String::with_capacity(term.len());
+    for c in term.to_lowercase().chars() {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use walker::{MarkdownFile, MarkdownFileList};
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(super::slugify("Rust"), "rust");
+        assert_eq!(super::slugify("  Rust Tips! "), "rust-tips");
+        assert_eq!(super::slugify("C++ / FFI"), "c-ffi");
+        assert_eq!(super::slugify("Café"), "café");
+        assert_eq!(super::slugify("???"), "");
+    }
+
+    #[test]
+    fn test_collect() {
+        let root = Path::new("tests/resources/input/taxonomy");
+        let files = MarkdownFileList::new(vec![
+            MarkdownFile::from_root(root, &root.join("intro.md")),
+            MarkdownFile::from_root(root, &root.join("guides/setup.md")),
+        ]);
+        let tags = super::Taxonomy::collect("tags", &files).unwrap();
+        let terms: Vec<(&str, &str, usize)> = tags.terms()
+            .iter()
+            .map(|x| (x.name(), x.slug(), x.pages().len()))
+            .collect();
+        assert_eq!(
+            terms,
+            vec![
+                ("Index", "_index", 1),
+                ("Getting Started", "getting-started", 2),
+                ("Rust", "rust", 1),
+            ]
+        );
+        assert_eq!(
+            tags.term_path(&tags.terms()[2]),
+            PathBuf::from("tags/rust.html")
+        );
+        // The index term does not take the place of the list of terms
+        assert_eq!(
+            tags.term_path(&tags.terms()[0]),
+            PathBuf::from("tags/_index.html")
+        );
+        assert_eq!(tags.index_path(), PathBuf::from("tags/index.html"));
+
+        let authors = super::Taxonomy::collect("authors", &files).unwrap();
+        assert_eq!(authors.terms().len(), 2);
+        assert_eq!(authors.terms()[0].name(), "Ann");
+        assert!(super::Taxonomy::collect("categories", &files)
+            .unwrap()
+            .terms()
+            .is_empty());
+    }
+}
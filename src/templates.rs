@@ -1,75 +1,329 @@
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use handlebars::{to_json, Handlebars};
 use serde_json::{Map, Value as Json};
 
 use MarkdownFileList;
+use walker::{Entry, MarkdownFile, Section};
 use config::Configuration;
-use Result;
+use front_matter::FrontMatter;
+use highlight::Highlight;
+use html::RenderedPage;
+use links::Backlink;
+use taxonomy::{Taxonomy, Term};
+use manifest;
 use file_utils;
+use {ErrorKind, Result};
 
-/// Construct a generated index page for the site from the list of files used.
-pub fn generate_index(files: &MarkdownFileList, config: &Configuration) -> Result<String> {
-    let data = populate_index_data(files, config);
-    build_template(&data, include_str!("../templates/index.hbs"))
+/// Name the container template is registered under.
+const CONTAINER: &str = "container";
+/// Name of the partial the container places the page within, which gives the
+/// page rendered with its own template under the same name.
+const CONTENT: &str = "content";
+/// Name of the template used for pages which do not select their own.
+const PAGE: &str = "page";
+const INDEX: &str = "index";
+/// Names of the templates used for the pages of every taxonomy, listing its
+/// terms and the pages given a term. A page template named after the taxonomy,
+/// or after it with the suffix for the pages of a term, is used for it instead.
+const TAXONOMY: &str = "taxonomy";
+const TERM: &str = "term";
+const TERM_SUFFIX: &str = "_term";
+/// Prefix from the pages of a taxonomy, within its directory, to the root.
+const TAXONOMY_ROOT: &str = "../";
+/// Extension of the template files within the templates directory.
+const TEMPLATE_EXTENSION: &str = "hbs";
+/// Directory within the templates directory holding the page templates.
+const PAGES_DIR: &str = "pages";
+/// Prefix of the names the page templates are registered under, keeping them
+/// apart from the partials.
+const PAGE_PREFIX: &str = "pages/";
+
+/// Handlebars templates for the pages of the site. Each template is compiled
+/// once, when the `Templates` are created, into a registry shared by every
+/// page.
+pub struct Templates {
+    handlebars: Handlebars,
+    // Names of the page templates, as pages select them
+    pages: BTreeSet<String>,
+    // Hash of the source of every template
+    fingerprint: u64,
+    live_reload: bool,
 }
 
-/// Render the index page using the provided template path filling the same
-/// variables as expected for the generated template
-pub fn render_index_with_template<P: AsRef<Path>>(
-    template_path: P,
-    files: &MarkdownFileList,
-    config: &Configuration,
-) -> Result<String> {
-    let data = populate_index_data(files, config);
-    let template_content = &file_utils::read_from_file(template_path)?;
-    build_template(&data, template_content)
+impl Templates {
+    /// Compile the templates of the site. The index page is rendered with the
+    /// `index_template` provided, otherwise the generated index is used.
+    ///
+    /// Within the `templates_dirs` the `container.hbs` and `page.hbs` files
+    /// replace the built in templates of the same name and any other `.hbs`
+    /// file is added as a partial. Those within their `pages` directory are
+    /// page templates, which pages may select as their own. The `taxonomy.hbs`
+    /// and `term.hbs` files likewise replace the templates for the pages of
+    /// the taxonomies. Templates in later directories replace those of the
+    /// same name before them.
+    pub fn new(index_template: Option<&str>, templates_dirs: &[PathBuf]) -> Result<Templates> {
+        let mut partials = BTreeMap::new();
+        let mut pages = BTreeMap::new();
+        for dir in templates_dirs {
+            partials.extend(read_templates(dir)?);
+            let pages_dir = dir.join(PAGES_DIR);
+            if pages_dir.is_dir() {
+                pages.extend(read_templates(&pages_dir)?);
+            }
+        }
+        let container = partials
+            .remove(CONTAINER)
+            .unwrap_or_else(|| include_str!("../templates/container.hbs").to_string());
+        let page = partials
+            .remove(PAGE)
+            .unwrap_or_else(|| include_str!("../templates/basic.hbs").to_string());
+        let index = index_template.unwrap_or(include_str!("../templates/index.hbs"));
+        let taxonomy = partials
+            .remove(TAXONOMY)
+            .unwrap_or_else(|| include_str!("../templates/taxonomy.hbs").to_string());
+        let term = partials
+            .remove(TERM)
+            .unwrap_or_else(|| include_str!("../templates/term.hbs").to_string());
+        let fingerprint = manifest::hash(&(
+            &container,
+            &page,
+            index,
+            &taxonomy,
+            &term,
+            include_str!("../templates/section.hbs"),
+            &partials,
+            &pages,
+        ));
+        pages.insert(PAGE.to_string(), page);
+        pages.insert(INDEX.to_string(), index.to_string());
+        pages.insert(TAXONOMY.to_string(), taxonomy);
+        pages.insert(TERM.to_string(), term);
+
+        let mut handlebars = Handlebars::new();
+        handlebars.register_template_string(CONTAINER, container)?;
+        handlebars.register_partial(CONTENT, format!("{{{{{{{}}}}}}}", CONTENT))?;
+        handlebars.register_partial("section", include_str!("../templates/section.hbs"))?;
+        for (name, content) in &partials {
+            handlebars.register_partial(name, content)?;
+        }
+        for (name, content) in &pages {
+            handlebars.register_template_string(&(PAGE_PREFIX.to_owned() + name), content)?;
+        }
+        Ok(Templates {
+            handlebars,
+            pages: pages.into_keys().collect(),
+            fingerprint,
+            live_reload: false,
+        })
+    }
+
+    /// Render the page template of the name within the container
+    fn render(&self, name: &str, mut data: Map<String, Json>) -> Result<String> {
+        let content = self.handlebars.render(&(PAGE_PREFIX.to_owned() + name), &data)?;
+        data.insert(CONTENT.to_string(), Json::String(content));
+        Ok(self.handlebars.render(CONTAINER, &data)?)
+    }
+
+    /// Returns a hash of the source of every template, so that a build can
+    /// tell when they differ from those the previous build used.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Include the script which reloads each page as the site is rebuilt
+    pub fn set_live_reload(&mut self, live_reload: bool) {
+        self.live_reload = live_reload;
+    }
+
+    /// Construct the index page for the site from the list of files used.
+    pub fn render_index(&self, files: &MarkdownFileList, config: &Configuration) -> Result<String> {
+        let data = populate_index_data(files, config, self.live_reload);
+        self.render(INDEX, data)
+    }
+
+    /// Construct the page listing every term of the taxonomy, provided to the
+    /// template under `terms` with the pages given each.
+    pub fn render_taxonomy(&self, taxonomy: &Taxonomy, config: &Configuration) -> Result<String> {
+        let title = config.title() + " - " + taxonomy.name();
+        let mut data = base_data(config, title, TAXONOMY_ROOT, self.live_reload);
+        data.insert("taxonomy".to_string(), Json::String(taxonomy.name().to_owned()));
+        data.insert("terms".to_string(), to_json(&taxonomy.terms()));
+        let name = self.pages.get(taxonomy.name()).map_or(TAXONOMY, |x| x.as_str());
+        self.render(name, data)
+    }
+
+    /// Construct the page listing the pages given a term of the taxonomy,
+    /// provided to the template under `pages`.
+    pub fn render_term(
+        &self,
+        taxonomy: &Taxonomy,
+        term: &Term,
+        config: &Configuration,
+    ) -> Result<String> {
+        let title = config.title() + " - " + term.name();
+        let mut data = base_data(config, title, TAXONOMY_ROOT, self.live_reload);
+        data.insert("taxonomy".to_string(), Json::String(taxonomy.name().to_owned()));
+        data.insert("term".to_string(), Json::String(term.name().to_owned()));
+        data.insert("slug".to_string(), Json::String(term.slug().to_owned()));
+        data.insert("pages".to_string(), to_json(&term.pages()));
+        let name = self.pages
+            .get(&(taxonomy.name().to_owned() + TERM_SUFFIX))
+            .map_or(TERM, |x| x.as_str());
+        self.render(name, data)
+    }
+
+    /// Take the HTML of a page and encapsulate with the correct tags. Will also
+    /// add the stylesheet. The `site_root` is the relative prefix from the page
+    /// back to the root of the site so that shared resources resolve from
+    /// nested pages. All front matter of the page is provided to the template
+    /// under `page`, the table of contents under `toc` and the pages linking
+    /// to it under `backlinks`. The `template` given in the front matter
+    /// selects the template used, returning an error if there is no template
+    /// of that name.
+    pub fn encapsulate_bare_html(
+        &self,
+        page: &RenderedPage,
+        backlinks: &[Backlink],
+        config: &Configuration,
+        title: String,
+        site_root: &str,
+        front_matter: &FrontMatter,
+    ) -> Result<String> {
+        let name = front_matter.template().map(|x| x.as_str()).unwrap_or(PAGE);
+        if !self.pages.contains(name) {
+            return Err(ErrorKind::Fail(format!(
+                "No template named {} for the page {}",
+                name, title
+            )).into());
+        }
+
+        let title = config.title() + " - " + &title;
+        let mut data = base_data(config, title, site_root, self.live_reload);
+        data.insert("page".to_string(), to_json(front_matter));
+        data.insert("toc".to_string(), to_json(&page.toc()));
+        data.insert("backlinks".to_string(), to_json(&backlinks));
+        data.insert(
+            "show_backlinks".to_string(),
+            Json::Bool(config.show_backlinks()),
+        );
+        data.insert(
+            "md_content".to_string(),
+            Json::String(page.content().to_owned()),
+        );
+
+        self.render(name, data)
+    }
+}
+
+impl fmt::Debug for Templates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Templates").finish()
+    }
 }
 
 /// Element provided to the Handlebars template for creating the index page.
 /// Each element represents a single Markdown document on the input.
-#[derive(Serialize)]
+#[derive(Serialize, Debug, Clone, Hash, PartialEq)]
 pub struct Element {
     header: String,
     file_path: String,
 }
 
+impl Element {
+    pub fn from(file: &MarkdownFile) -> Element {
+        Element {
+            header: file.get_heading().to_owned(),
+            file_path: file.get_link_path(),
+        }
+    }
+}
+
+/// Directory of the site provided to the Handlebars template for creating the
+/// index page. Holds the pages directly within it and any nested directories
+/// so templates are able to recurse over the hierarchy, along with both of
+/// them together in the order they are listed.
+#[derive(Serialize, Clone)]
+pub struct SectionElement {
+    name: String,
+    path: String,
+    pages: Vec<Element>,
+    sections: Vec<SectionElement>,
+    entries: Vec<EntryElement>,
+}
+
+/// Page or directory within a directory of the index, under `page` or
+/// `section` respectively.
+#[derive(Serialize, Clone)]
+pub struct EntryElement {
+    page: Option<Element>,
+    section: Option<SectionElement>,
+}
+
+impl SectionElement {
+    fn from(section: &Section, files: &MarkdownFileList) -> SectionElement {
+        let pages: Vec<Element> = files
+            .get_section_files(section)
+            .iter()
+            .map(|x| Element::from(x))
+            .collect();
+        let sections: Vec<SectionElement> = section
+            .get_sections()
+            .iter()
+            .map(|x| SectionElement::from(x, files))
+            .collect();
+        let entries = section
+            .get_entries()
+            .iter()
+            .map(|entry| match *entry {
+                Entry::Page(index) => EntryElement {
+                    page: Some(Element::from(&files.get_files()[index])),
+                    section: None,
+                },
+                Entry::Section(index) => EntryElement {
+                    page: None,
+                    section: Some(sections[index].clone()),
+                },
+            })
+            .collect();
+        SectionElement {
+            name: section.get_name().to_owned(),
+            path: section.get_path().to_string_lossy().replace('\\', "/"),
+            pages,
+            sections,
+            entries,
+        }
+    }
+}
+
 /// Populate the data map used to populate the index page template
-fn populate_index_data(files: &MarkdownFileList, config: &Configuration) -> Map<String, Json> {
-    let mut data = Map::new();
-    data.insert(
-        "stylesheet".to_string(),
-        Json::Array(
-            config
-                .stylesheet()
-                .iter()
-                .map(|x| Json::String(x.to_owned()))
-                .collect(),
-        ),
-    );
+fn populate_index_data(
+    files: &MarkdownFileList,
+    config: &Configuration,
+    live_reload: bool,
+) -> Map<String, Json> {
+    let mut data = base_data(config, config.title() + " - Home", "", live_reload);
+    let elements: Vec<Element> = files.get_files().iter().map(Element::from).collect();
+    data.insert("element".to_string(), to_json(&elements));
     data.insert(
-        "title".to_string(),
-        Json::String(config.title() + " - Home"),
+        "site".to_string(),
+        to_json(&SectionElement::from(files.get_root(), files)),
     );
-    let elements: Vec<Element> = files
-        .get_files()
-        .iter()
-        .map(|x| Element {
-            header: x.get_heading().to_owned(),
-            file_path: x.get_file_name(),
-        })
-        .collect();
-    data.insert("element".to_string(), to_json(&elements));
 
     data
 }
 
-/// Take a HTML string and encapsulate with the correct tags. Will also add the stylesheet.
-pub fn encapsulate_bare_html(
-    content: String,
+/// Data every template is given, for a page with the title and the relative
+/// prefix `site_root` back to the root of the site.
+fn base_data(
     config: &Configuration,
     title: String,
-) -> Result<String> {
+    site_root: &str,
+    live_reload: bool,
+) -> Map<String, Json> {
     let mut data = Map::new();
     data.insert(
         "stylesheet".to_string(),
@@ -81,62 +335,195 @@ pub fn encapsulate_bare_html(
                 .collect(),
         ),
     );
+    data.insert("title".to_string(), Json::String(title));
+    data.insert("site_root".to_string(), Json::String(site_root.to_owned()));
+    data.insert("use_cdn".to_string(), Json::Bool(config.use_cdn()));
+    insert_highlight_data(&mut data, config);
+    data.insert("live_reload".to_string(), Json::Bool(live_reload));
+    data
+}
+
+/// Add whether the code blocks need highlight.js or the stylesheet of the
+/// colour scheme to the data of a template
+fn insert_highlight_data(data: &mut Map<String, Json>, config: &Configuration) {
+    let highlight = Highlight::from_config(config).unwrap_or(Highlight::Client);
     data.insert(
-        "title".to_string(),
-        Json::String(config.title() + " - " + &title),
+        "client_highlight".to_string(),
+        Json::Bool(highlight.is_client()),
+    );
+    data.insert(
+        "highlight_stylesheet".to_string(),
+        Json::Bool(highlight.stylesheet().is_some()),
     );
-    data.insert("md_content".to_string(), Json::String(content));
-
-    build_template(&data, include_str!("../templates/basic.hbs"))
 }
 
-/// Constructs Handlebars template from the provided variable data. Uses partial templates
-/// to produce consistent container. Returns error if the template failed to compile.
-fn build_template(data: &Map<String, Json>, template_content: &str) -> Result<String> {
-    let mut handlebars = Handlebars::new();
-    // Render the partials
-    handlebars.register_template_string("container", include_str!("../templates/container.hbs"))?;
-    handlebars.register_partial("content", template_content)?;
-
-    // That's all we need to build this thing
-    let rendered = handlebars.render("container", &data)?;
-    Ok(rendered)
+/// Read each of the templates within the directory, by their name without the
+/// extension.
+fn read_templates(dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut templates = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|x| x.to_str()) != Some(TEMPLATE_EXTENSION) {
+            continue;
+        }
+        if let Some(name) = path.file_stem().and_then(|x| x.to_str()) {
+            debug!("Using user template {}", path.display());
+            templates.insert(name.to_string(), file_utils::read_from_file(&path)?);
+        }
+    }
+    Ok(templates)
 }
 
 #[cfg(test)]
 mod tests {
     use test_utils;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
     use walker::MarkdownFile;
     use config;
+    use front_matter;
+    use serde_json::Value as Json;
+    use pulldown_cmark::Parser;
+    use html;
+    use links::SiteLinks;
+    use taxonomy::Taxonomy;
     #[test]
     fn test_generate_index() {
         let config = config::Configuration::from("tests/resources/input/site/mdup.yml").unwrap();
         let expected = include_str!("../tests/resources/output/index_good.html");
-        let actual = super::generate_index(
-            &super::MarkdownFileList::new(vec![
-                MarkdownFile::from(&Path::new("tests/resources/input/site/second-page.md")),
-                MarkdownFile::from(&Path::new("tests/resources/input/site/all_test.md")),
-            ]),
-            &config,
-        ).unwrap();
+        let root = Path::new("tests/resources/input/site");
+        let templates = super::Templates::new(None, &[]).unwrap();
+        let actual = templates
+            .render_index(
+                &super::MarkdownFileList::new(vec![
+                    MarkdownFile::from_root(root, &root.join("second-page.md")),
+                    MarkdownFile::from_root(root, &root.join("nested/nested-page.md")),
+                    MarkdownFile::from_root(root, &root.join("all_test.md")),
+                ]),
+                &config,
+            )
+            .unwrap();
         test_utils::compare_string_content(expected, &actual);
     }
 
+    #[test]
+    fn test_index_data_hierarchy() {
+        let config = config::Configuration::from("tests/resources/input/site/mdup.yml").unwrap();
+        let root = Path::new("tests/resources/input/site");
+        let files = super::MarkdownFileList::new(vec![
+            MarkdownFile::from_root(root, &root.join("nested/nested-page.md")),
+            MarkdownFile::from_root(root, &root.join("second-page.md")),
+        ]);
+        let data = super::populate_index_data(&files, &config, false);
+        let site = &data["site"];
+        assert_eq!(site["pages"][0]["file_path"], Json::from("second-page"));
+        assert_eq!(site["sections"][0]["name"], Json::from("nested"));
+        assert_eq!(
+            site["sections"][0]["pages"][0]["file_path"],
+            Json::from("nested/nested-page")
+        );
+        assert_eq!(
+            site["sections"][0]["pages"][0]["header"],
+            Json::from("Nested Page")
+        );
+        assert_eq!(site["entries"][0]["page"]["file_path"], Json::from("second-page"));
+        assert_eq!(site["entries"][1]["section"]["name"], Json::from("nested"));
+    }
+
     #[test]
     fn test_index_from_template() {
         let config = config::Configuration::from(
             "tests/resources/input/test_conf_user_template.yml",
         ).unwrap();
         let expected = include_str!("../tests/resources/output/user_index_good.html");
-        let actual = super::render_index_with_template(
-            "tests/resources/input/index_test.hbs",
-            &super::MarkdownFileList::new(vec![
-                MarkdownFile::from(&Path::new("tests/resources/input/site/second-page.md")),
-                MarkdownFile::from(&Path::new("tests/resources/input/site/all_test.md")),
-            ]),
+        let index_template = include_str!("../tests/resources/input/index_test.hbs");
+        let templates = super::Templates::new(Some(index_template), &[]).unwrap();
+        let actual = templates
+            .render_index(
+                &super::MarkdownFileList::new(vec![
+                    MarkdownFile::from(&Path::new("tests/resources/input/site/second-page.md")),
+                    MarkdownFile::from(&Path::new("tests/resources/input/site/all_test.md")),
+                ]),
+                &config,
+            )
+            .unwrap();
+        test_utils::compare_string_content(expected, &actual);
+    }
+
+    #[test]
+    fn test_user_templates() {
+        let config = config::Configuration::default();
+        let templates = super::Templates::new(
+            None,
+            &[
+                PathBuf::from("tests/resources/input/theme/templates"),
+                PathBuf::from("tests/resources/input/templates"),
+            ],
+        ).unwrap();
+        let page = html::consume(
+            Parser::new("Content"),
             &config,
+            &SiteLinks::default().page("page"),
+        );
+        let render = |front_matter: &str| {
+            let (front_matter, _) = front_matter::split(front_matter).unwrap();
+            templates.encapsulate_bare_html(
+                &page,
+                &[],
+                &config,
+                "Page".to_string(),
+                "",
+                &front_matter,
+            )
+        };
+
+        let actual = render("---\nauthor: Someone\n---\n").unwrap();
+        assert!(actual.contains("<article class=\"custom-page\">"));
+        assert!(actual.contains("<footer>Written by Someone</footer>"));
+        assert!(!actual.contains("theme-page"));
+        assert!(actual.contains("<title>Title - Page</title>"));
+
+        let actual = render("---\ntemplate: wide\n---\n").unwrap();
+        assert!(actual.contains("<div class=\"wide\">"));
+        assert!(!actual.contains("custom-page"));
+
+        assert!(render("---\ntemplate: missing\n---\n").is_err());
+        // Partials are not page templates
+        assert!(render("---\ntemplate: footer\n---\n").is_err());
+    }
+
+    #[test]
+    fn test_taxonomy_templates() {
+        let config = config::Configuration::default();
+        let templates = super::Templates::new(
+            None,
+            &[PathBuf::from("tests/resources/input/templates")],
         ).unwrap();
-        test_utils::compare_string_content(expected, &actual);
+        let files = super::MarkdownFileList::new(vec![]);
+        let render = |name: &str| {
+            let taxonomy = Taxonomy::collect(name, &files).unwrap();
+            templates.render_taxonomy(&taxonomy, &config).unwrap()
+        };
+
+        // The tags partial leaves the page of the taxonomy alone
+        let actual = render("tags");
+        assert!(actual.contains("<h1 class=\"taxonomy-name\">tags</h1>"));
+        assert!(!actual.contains("tag-list"));
+
+        let actual = render("authors");
+        assert!(actual.contains("<h1 class=\"authors\">authors</h1>"));
+        assert!(!actual.contains("taxonomy-name"));
+    }
+
+    #[test]
+    fn test_live_reload_script() {
+        let config = config::Configuration::from("tests/resources/input/site/mdup.yml").unwrap();
+        let files = super::MarkdownFileList::new(vec![]);
+        let script = "<script src=\"/__made-up/live-reload.js\"></script>";
+        let mut templates = super::Templates::new(None, &[]).unwrap();
+        let actual = templates.render_index(&files, &config).unwrap();
+        assert!(!actual.contains(script));
+        templates.set_live_reload(true);
+        let actual = templates.render_index(&files, &config).unwrap();
+        assert!(actual.contains(script));
     }
 }
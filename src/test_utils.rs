@@ -1,9 +1,20 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process;
+
 /// Strips all whitespace anywhere within the string. Useful for comparing
 /// strings when only caring about content.
 fn strip_all_whitespace(string: &str) -> String {
     string.chars().filter(|x| !x.is_whitespace()).collect()
 }
 
+/// Determines if the two strings provided have the same non-whitespace content.
+pub fn same_string_content(expected: &str, actual: &str) -> bool {
+    strip_all_whitespace(expected) == strip_all_whitespace(actual)
+}
+
 /// Asserts the two strings provided have the same non-whitespace content.
 pub fn compare_string_content(expected: &str, actual: &str) {
     let expected = strip_all_whitespace(&expected);
@@ -11,3 +22,62 @@ pub fn compare_string_content(expected: &str, actual: &str) {
 
     assert_eq!(expected, actual);
 }
+
+/// Creates an empty directory for a test to work within, unique to the test
+/// process so that concurrent runs do not interfere with each other.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("made-up-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Unable to create temporary directory");
+    dir
+}
+
+/// Example from a specification file written in the format of the CommonMark
+/// specification.
+#[derive(Debug)]
+pub struct SpecExample {
+    /// Number of the example within the specification, starting at 1
+    pub number: usize,
+    pub section: String,
+    pub markdown: String,
+    pub html: String,
+}
+
+/// Reads all examples from the specification file at the provided path, in
+/// the order they are numbered by the specification.
+pub fn read_spec_examples(path: &str) -> Vec<SpecExample> {
+    const FENCE: &str = "````````````````````````````````";
+    let mut content = String::new();
+    File::open(path)
+        .and_then(|mut x| x.read_to_string(&mut content))
+        .expect("Unable to read specification");
+
+    let mut examples = vec![];
+    let mut section = String::new();
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        if line.starts_with('#') {
+            section = line.trim_start_matches('#').trim().to_string();
+        } else if line.starts_with(FENCE) && line.ends_with("example") {
+            let mut markdown = String::new();
+            let mut html = String::new();
+            let mut in_html = false;
+            for line in lines.by_ref().take_while(|x| *x != FENCE) {
+                if line == "." && !in_html {
+                    in_html = true;
+                    continue;
+                }
+                let target = if in_html { &mut html } else { &mut markdown };
+                target.push_str(&line.replace('→', "\t"));
+                target.push('\n');
+            }
+            examples.push(SpecExample {
+                number: examples.len() + 1,
+                section: section.clone(),
+                markdown,
+                html,
+            });
+        }
+    }
+    examples
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use {ErrorKind, Result};
+
+/// Name of the theme used when none is configured.
+pub const DEFAULT_THEME: &str = "light";
+/// Directory within a local theme holding its Handlebars templates.
+const TEMPLATES_DIR: &str = "templates";
+/// Directory within a local theme holding the files copied to the site.
+const STATIC_DIR: &str = "static";
+
+/// Vendored stylesheet of Bootstrap, linked from its CDN instead when the
+/// configuration asks for it.
+const BOOTSTRAP_CSS_NAME: &str = "bootstrap.css";
+
+const BOOTSTRAP_CSS: &str = include_str!("../theme/bootstrap.css");
+const COPY_CODE_JS: &str = include_str!("../theme/copy-code.js");
+const HIGHLIGHT_CSS: &str = include_str!("../theme/highlight.css");
+const HIGHLIGHT_JS: &str = include_str!("../theme/highlight.js");
+const TOMORROW_NIGHT_CSS: &str = include_str!("../theme/tomorrow-night.css");
+const LIGHT_CSS: &str = include_str!("../theme/made-up.css");
+const DARK_CSS: &str = concat!(
+    include_str!("../theme/made-up.css"),
+    include_str!("../theme/dark.css")
+);
+
+/// Look of the generated site, either one of the themes built into Made-Up or
+/// a local directory. A local theme builds upon the default theme, with its
+/// `templates` directory providing templates as the `templates_dir` of the
+/// configuration does and its `static` directory holding files copied to the
+/// root of the site, replacing those of the default theme with the same name.
+#[derive(Debug)]
+pub struct Theme {
+    name: String,
+    dir: Option<PathBuf>,
+}
+
+impl Theme {
+    /// Find the theme of the given name, which is either a built in theme or
+    /// the path of a theme directory relative to the root directory. Returns
+    /// an error if it is neither.
+    pub fn load<P: AsRef<Path>>(root_dir: P, name: &str) -> Result<Theme> {
+        if built_in_assets(name).is_some() {
+            return Ok(Theme {
+                name: name.to_string(),
+                dir: None,
+            });
+        }
+        let dir = root_dir.as_ref().join(name);
+        if !dir.is_dir() {
+            return Err(ErrorKind::Fail(format!(
+                "Theme {} is not a built in theme or a directory within {}",
+                name,
+                root_dir.as_ref().display()
+            )).into());
+        }
+        Ok(Theme {
+            name: name.to_string(),
+            dir: Some(dir),
+        })
+    }
+
+    /// Returns the directory of templates provided by the theme, if any
+    pub fn templates_dir(&self) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|x| x.join(TEMPLATES_DIR))
+            .filter(|x| x.is_dir())
+    }
+
+    /// Returns every file the theme adds to the site, by its path within the
+    /// output directory. The vendored Bootstrap is left out when `use_cdn` is
+    /// set as the pages link to the CDN instead.
+    pub fn assets(&self, use_cdn: bool) -> Result<BTreeMap<String, Vec<u8>>> {
+        let built_in = built_in_assets(&self.name).unwrap_or_else(|| {
+            built_in_assets(DEFAULT_THEME).expect("Default theme is not built in")
+        });
+        let mut assets: BTreeMap<String, Vec<u8>> = built_in
+            .iter()
+            .filter(|&&(name, _)| !(use_cdn && name == BOOTSTRAP_CSS_NAME))
+            .map(|&(name, content)| (name.to_string(), content.as_bytes().to_vec()))
+            .collect();
+
+        let static_dir = match self.dir {
+            Some(ref dir) => dir.join(STATIC_DIR),
+            None => return Ok(assets),
+        };
+        if !static_dir.is_dir() {
+            return Ok(assets);
+        }
+        for entry in WalkDir::new(&static_dir) {
+            let entry = entry.map_err(io::Error::from)?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let name = entry
+                .path()
+                .strip_prefix(&static_dir)
+                .expect("Theme file outside of its directory")
+                .to_string_lossy()
+                .replace('\\', "/");
+            assets.insert(name, fs::read(entry.path())?);
+        }
+        Ok(assets)
+    }
+}
+
+/// Files of the built in theme with the given name, `None` if there is no
+/// such theme.
+fn built_in_assets(name: &str) -> Option<[(&'static str, &'static str); 6]> {
+    let made_up_css = match name {
+        "light" => LIGHT_CSS,
+        "dark" => DARK_CSS,
+        _ => return None,
+    };
+    Some([
+        (BOOTSTRAP_CSS_NAME, BOOTSTRAP_CSS),
+        ("copy-code.js", COPY_CODE_JS),
+        ("highlight.css", HIGHLIGHT_CSS),
+        ("highlight.js", HIGHLIGHT_JS),
+        ("made-up.css", made_up_css),
+        ("tomorrow-night.css", TOMORROW_NIGHT_CSS),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    const ROOT: &str = "tests/resources/input";
+
+    #[test]
+    fn test_built_in_themes() {
+        let light = super::Theme::load(ROOT, "light").unwrap();
+        assert_eq!(light.templates_dir(), None);
+        let assets = light.assets(false).unwrap();
+        assert_eq!(
+            assets.keys().collect::<Vec<_>>(),
+            vec![
+                "bootstrap.css",
+                "copy-code.js",
+                "highlight.css",
+                "highlight.js",
+                "made-up.css",
+                "tomorrow-night.css",
+            ]
+        );
+        assert_eq!(assets["made-up.css"], super::LIGHT_CSS.as_bytes());
+
+        let dark = super::Theme::load(ROOT, "dark").unwrap();
+        let assets = dark.assets(false).unwrap();
+        let made_up_css = String::from_utf8(assets["made-up.css"].clone()).unwrap();
+        assert!(made_up_css.starts_with(super::LIGHT_CSS));
+        assert!(made_up_css.contains("background-color: #1d1f21;"));
+    }
+
+    #[test]
+    fn test_local_theme() {
+        let theme = super::Theme::load(ROOT, "theme").unwrap();
+        assert_eq!(
+            theme.templates_dir(),
+            Some(Path::new(ROOT).join("theme/templates"))
+        );
+        let assets = theme.assets(false).unwrap();
+        assert_eq!(assets["made-up.css"], b"body { color: teal; }\n".to_vec());
+        assert_eq!(assets["fonts/note.txt"], b"Theme font\n".to_vec());
+        assert_eq!(assets["highlight.js"], super::HIGHLIGHT_JS.as_bytes());
+    }
+
+    #[test]
+    fn test_cdn_assets() {
+        let light = super::Theme::load(ROOT, "light").unwrap();
+        let assets = light.assets(true).unwrap();
+        assert!(!assets.contains_key("bootstrap.css"));
+        assert!(assets.contains_key("made-up.css"));
+    }
+
+    #[test]
+    fn test_unknown_theme() {
+        assert!(super::Theme::load(ROOT, "missing").is_err());
+    }
+}
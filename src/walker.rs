@@ -1,51 +1,278 @@
-use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use walkdir::{DirEntry, WalkDir, WalkDirIterator};
 use pulldown_cmark::{Event, Parser, Tag};
 use file_utils;
+use front_matter::{self, FrontMatter};
+use include::{self, Origin};
+use order::{PageOrder, SortKey};
+use ErrorKind;
 
-/// Wrapper of a list of Markdown files. With end goal to be able to convey the
-/// hierarchy.
+/// Wrapper of a list of Markdown files which conveys the hierarchy of the
+/// site. The files are held in a flat list, ordered as a depth first walk of
+/// the directories, with the tree of `Section`s referring into it.
 pub struct MarkdownFileList {
-    // Considering maintaining directory structure by Map<Vec<>>
     files: Vec<MarkdownFile>,
+    root: Section,
 }
 
 impl MarkdownFileList {
+    /// Group the files by directory, sorted by file name within each.
     pub fn new(files: Vec<MarkdownFile>) -> MarkdownFileList {
-        let mut sorted_files = files;
-        sorted_files.sort_by(|a, b| a.get_file_name().cmp(&b.get_file_name()));
+        MarkdownFileList::build(files, &PageOrder::default())
+    }
+
+    /// Reorder the files within each directory in the given order
+    pub fn sorted(self, order: &PageOrder) -> MarkdownFileList {
+        MarkdownFileList::build(self.files, order)
+    }
+
+    fn build(files: Vec<MarkdownFile>, order: &PageOrder) -> MarkdownFileList {
+        let mut tree = DirectoryNode::default();
+        for file in files {
+            tree.insert(file);
+        }
+        let mut flat_files = vec![];
+        let root = tree.flatten(String::new(), PathBuf::new(), order, &mut flat_files);
         MarkdownFileList {
-            files: sorted_files,
+            files: flat_files,
+            root,
         }
     }
+
     /// Get all Markdown files
     pub fn get_files(&self) -> &Vec<MarkdownFile> {
         &self.files
     }
+
+    /// Get the section representing the root directory of the site
+    pub fn get_root(&self) -> &Section {
+        &self.root
+    }
+
+    /// Get the Markdown files which sit directly within the provided section
+    pub fn get_section_files(&self, section: &Section) -> Vec<&MarkdownFile> {
+        section.pages.iter().map(|&index| &self.files[index]).collect()
+    }
 }
 
+/// A directory within the site. Holds the position of each of its pages in the
+/// flat list of files along with any nested directories.
+#[derive(Debug)]
+pub struct Section {
+    name: String,
+    path: PathBuf,
+    pages: Vec<usize>,
+    sections: Vec<Section>,
+    // Pages and directories together, in the order they are listed
+    entries: Vec<Entry>,
+}
+
+/// Page or nested directory within a `Section`, by its position within the
+/// flat list of files or the sections of the directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entry {
+    Page(usize),
+    Section(usize),
+}
+
+impl Section {
+    /// Return the name of the directory, empty for the root of the site
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the path of the directory relative to the root directory
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Return the directories nested within this one
+    pub fn get_sections(&self) -> &Vec<Section> {
+        &self.sections
+    }
+
+    /// Return the pages and directories within this one, in the order they
+    /// are listed
+    pub fn get_entries(&self) -> &Vec<Entry> {
+        &self.entries
+    }
+}
+
+/// Intermediate tree used to group the files by directory before they are
+/// flattened into the `MarkdownFileList`.
+#[derive(Default)]
+struct DirectoryNode {
+    files: Vec<MarkdownFile>,
+    children: BTreeMap<String, DirectoryNode>,
+}
+
+impl DirectoryNode {
+    /// Place the file within the node matching its parent directory
+    fn insert(&mut self, file: MarkdownFile) {
+        let directories: Vec<String> = file.relative_path
+            .parent()
+            .map(|parent| {
+                parent
+                    .components()
+                    .map(|x| x.as_os_str().to_string_lossy().into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut node = self;
+        for directory in directories {
+            node = node.children.entry(directory).or_default();
+        }
+        node.files.push(file);
+    }
+
+    /// Move the files into `flat_files`, in the order given within each
+    /// directory, and return the matching `Section`. The pages and nested
+    /// directories are sorted as one list, so that the files of a directory
+    /// listed before a page come before it.
+    fn flatten(
+        self,
+        name: String,
+        path: PathBuf,
+        order: &PageOrder,
+        flat_files: &mut Vec<MarkdownFile>,
+    ) -> Section {
+        let mut children: Vec<(SortKey, Child)> = self.files
+            .into_iter()
+            .map(|x| (order.file_key(&path, &x), Child::File(Box::new(x))))
+            .chain(
+                self.children
+                    .into_iter()
+                    .map(|(name, x)| (order.directory_key(&path, &name), Child::Directory(name, x))),
+            )
+            .collect();
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut pages = vec![];
+        let mut sections = vec![];
+        let mut entries = vec![];
+        for (_, child) in children {
+            match child {
+                Child::File(file) => {
+                    entries.push(Entry::Page(flat_files.len()));
+                    pages.push(flat_files.len());
+                    flat_files.push(*file);
+                }
+                Child::Directory(child_name, child) => {
+                    entries.push(Entry::Section(sections.len()));
+                    let child_path = path.join(&child_name);
+                    sections.push(child.flatten(child_name, child_path, order, flat_files));
+                }
+            }
+        }
+        Section {
+            name,
+            path,
+            pages,
+            sections,
+            entries,
+        }
+    }
+}
+
+/// File or named directory within a `DirectoryNode`, to be sorted together.
+enum Child {
+    File(Box<MarkdownFile>),
+    Directory(String, DirectoryNode),
+}
+
+/// Markdown file of the site. The heading, front matter and included files
+/// are cached once read, behind a `Mutex` so that files can be rendered on
+/// multiple threads.
 #[derive(Debug)]
 pub struct MarkdownFile {
     path: PathBuf,
-    heading: RefCell<String>,
+    relative_path: PathBuf,
+    heading: Mutex<String>,
+    front_matter: Mutex<Option<FrontMatter>>,
+    dependencies: Mutex<Option<Vec<PathBuf>>>,
 }
 
 impl MarkdownFile {
-    /// Creates a `MarkdownFile` from the provided path
+    /// Creates a `MarkdownFile` from the provided path. The file is treated as
+    /// if it were at the top of the site.
     pub fn from(path: &Path) -> MarkdownFile {
+        let relative_path = path.file_name()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| path.to_path_buf());
         MarkdownFile {
             path: path.to_path_buf(),
-            heading: RefCell::new(String::new()),
+            relative_path,
+            heading: Mutex::new(String::new()),
+            front_matter: Mutex::new(None),
+            dependencies: Mutex::new(None),
+        }
+    }
+
+    /// Creates a `MarkdownFile` for the provided path which was found within
+    /// `root_dir`, remembering where it sits relative to the root.
+    pub fn from_root(root_dir: &Path, path: &Path) -> MarkdownFile {
+        match path.strip_prefix(root_dir) {
+            Ok(relative_path) => MarkdownFile {
+                path: path.to_path_buf(),
+                relative_path: relative_path.to_path_buf(),
+                heading: Mutex::new(String::new()),
+                front_matter: Mutex::new(None),
+                dependencies: Mutex::new(None),
+            },
+            Err(_) => MarkdownFile::from(path),
         }
     }
+
     /// Return the path of the Markdown file
     pub fn get_path(&self) -> &PathBuf {
         &self.path
     }
 
+    /// Return the path of the Markdown file relative to the root directory
+    pub fn get_relative_path(&self) -> &PathBuf {
+        &self.relative_path
+    }
+
+    /// Return the path, relative to the output directory, that the generated
+    /// HTML page for this file will be written to.
+    pub fn get_output_path(&self) -> PathBuf {
+        self.relative_path.with_extension("html")
+    }
+
+    /// Return the output path without the `.html` extension, using forward
+    /// slashes so it can be used directly in links.
+    pub fn get_link_path(&self) -> String {
+        let mut components: Vec<String> = self.relative_path
+            .parent()
+            .map(|parent| {
+                parent
+                    .components()
+                    .map(|x| x.as_os_str().to_string_lossy().into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        components.push(self.get_file_name());
+        components.join("/")
+    }
+
+    /// Return the number of directories between the root directory and this
+    /// file.
+    pub fn get_depth(&self) -> usize {
+        self.relative_path
+            .parent()
+            .map(|parent| parent.components().count())
+            .unwrap_or(0)
+    }
+
+    /// Return the relative prefix needed to get from the generated page back
+    /// to the root of the site, i.e. `../../` for a file two directories deep.
+    pub fn get_site_root(&self) -> String {
+        "../".repeat(self.get_depth())
+    }
+
     /// Return the name of the Markdown file
     pub fn get_file_name(&self) -> String {
         self.path
@@ -56,11 +283,87 @@ impl MarkdownFile {
             .to_string()
     }
 
-    /// Return the main heading of the Markdown file
+    /// Read the Markdown file with the files it includes in place. Returns an
+    /// error if it or any of the included files could not be read.
+    pub fn read_source(&self) -> ::Result<String> {
+        self.expand_source().map(|(content, _)| content)
+    }
+
+    /// Read the Markdown file with the files it includes in place, along with
+    /// the origin of each line.
+    fn expand_source(&self) -> ::Result<(String, Vec<Origin>)> {
+        let content = file_utils::read_from_file(&self.path)?;
+        let (expanded, dependencies, origins) = include::expand(&content, &self.path)?;
+        let mut cached = self.dependencies.lock().expect("Dependency cache poisoned");
+        *cached = Some(dependencies);
+        Ok((expanded, origins))
+    }
+
+    /// Whether the file includes the file at `path`, directly or through
+    /// another included file. A file which can not be read depends on nothing.
+    pub fn depends_on(&self, path: &Path) -> bool {
+        let cached = self.dependencies
+            .lock()
+            .expect("Dependency cache poisoned")
+            .clone();
+        let dependencies = match cached {
+            Some(dependencies) => dependencies,
+            None => {
+                let _ = self.read_source();
+                self.dependencies
+                    .lock()
+                    .expect("Dependency cache poisoned")
+                    .clone()
+                    .unwrap_or_default()
+            }
+        };
+        let path = file_utils::normalize_path(path);
+        dependencies.contains(&path)
+    }
+
+    /// Read the Markdown file, returning its front matter, the Markdown
+    /// content following it and the file and line each line of the content
+    /// was written on. Returns an error if the file could not be read or the
+    /// front matter is not valid YAML.
+    pub fn read_content(&self) -> ::Result<(FrontMatter, String, Vec<Origin>)> {
+        let (content, origins) = self.expand_source()?;
+        let (front_matter, body) = front_matter::split(&content).map_err(|err| {
+            ErrorKind::Fail(format!(
+                "Invalid front matter in {}: {}",
+                self.path.display(),
+                err
+            ))
+        })?;
+        let mut cached = self.front_matter.lock().expect("Front matter cache poisoned");
+        *cached = Some(front_matter.clone());
+        let skipped = content[..content.len() - body.len()].matches('\n').count();
+        Ok((front_matter, body.to_owned(), origins[skipped..].to_vec()))
+    }
+
+    /// Return the front matter of the Markdown file. Files without front
+    /// matter will have the default, empty, front matter.
+    pub fn get_front_matter(&self) -> ::Result<FrontMatter> {
+        let cached = self.front_matter
+            .lock()
+            .expect("Front matter cache poisoned")
+            .clone();
+        if let Some(front_matter) = cached {
+            return Ok(front_matter);
+        }
+        self.read_content().map(|(front_matter, _, _)| front_matter)
+    }
+
+    /// Return the main heading of the Markdown file. The title given in the
+    /// front matter will be used over the first header 1 when present.
     pub fn get_heading(&self) -> String {
-        if self.heading.borrow().is_empty() {
-            let content = file_utils::read_from_file(&self.path)
-                .expect(&format!("Unable to read Markdown file: {:?}", self.path));
+        let mut heading = self.heading.lock().expect("Heading cache poisoned");
+        if heading.is_empty() {
+            let (front_matter, content, _) = self.read_content()
+                .unwrap_or_else(|_| panic!("Unable to read Markdown file: {:?}", self.path));
+            if let Some(title) = front_matter.title() {
+                heading.push_str(title);
+                return title.to_owned();
+            }
             let parser = Parser::new(&content);
             let mut iter = parser.into_iter();
             let mut opt_header = None;
@@ -81,11 +384,12 @@ impl MarkdownFile {
                     }
                 }
             }
-            let result = opt_header.expect(&format!("No header 1 found for {:?}", self.path));
-            self.heading.borrow_mut().push_str(&result);
+            let result =
+                opt_header.unwrap_or_else(|| panic!("No header 1 found for {:?}", self.path));
+            heading.push_str(&result);
             result
         } else {
-            self.heading.borrow().clone()
+            heading.clone()
         }
     }
 }
@@ -118,7 +422,7 @@ fn is_excluded(entry: &DirEntry) -> bool {
 /// this also includes any Markdown files beginning with an underscore.
 pub fn find_markdown_files<P: AsRef<Path>>(root_dir: P) -> Result<Vec<MarkdownFile>, io::Error> {
     let mut files = vec![];
-    let files_to_check = WalkDir::new(root_dir)
+    let files_to_check = WalkDir::new(root_dir.as_ref())
         .into_iter()
         .filter_entry(|file| !is_excluded(file));
     for entry in files_to_check {
@@ -126,7 +430,7 @@ pub fn find_markdown_files<P: AsRef<Path>>(root_dir: P) -> Result<Vec<MarkdownFi
         let path = entry.path();
         if is_accepted_markdown_file(path) {
             debug!("Adding file {:?}", path);
-            files.push(MarkdownFile::from(path));
+            files.push(MarkdownFile::from_root(root_dir.as_ref(), path));
         }
     }
 
@@ -135,18 +439,108 @@ pub fn find_markdown_files<P: AsRef<Path>>(root_dir: P) -> Result<Vec<MarkdownFi
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
-    use std::cell::RefCell;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+    use file_utils;
+    use test_utils;
+    use super::MarkdownFile;
 
     #[test]
     fn test_get_file_name() {
         let file = super::MarkdownFile {
             path: PathBuf::from("resources/tester.md"),
-            heading: RefCell::new(String::new()),
+            relative_path: PathBuf::from("tester.md"),
+            heading: Mutex::new(String::new()),
+            front_matter: Mutex::new(None),
+            dependencies: Mutex::new(None),
         };
         assert_eq!(file.get_file_name(), "tester");
     }
 
+    #[test]
+    fn test_nested_output_path() {
+        let file = MarkdownFile::from_root(
+            Path::new("site"),
+            Path::new("site/nested/deeper/setup.md"),
+        );
+        assert_eq!(
+            file.get_output_path(),
+            PathBuf::from("nested/deeper/setup.html")
+        );
+        assert_eq!(file.get_link_path(), "nested/deeper/setup");
+        assert_eq!(file.get_depth(), 2);
+        assert_eq!(file.get_site_root(), "../../");
+    }
+
+    #[test]
+    fn test_top_level_output_path() {
+        let file = MarkdownFile::from_root(Path::new("site"), Path::new("site/setup.md"));
+        assert_eq!(file.get_output_path(), PathBuf::from("setup.html"));
+        assert_eq!(file.get_link_path(), "setup");
+        assert_eq!(file.get_site_root(), "");
+    }
+
+    #[test]
+    fn test_file_list_hierarchy() {
+        let root = Path::new("site");
+        let list = super::MarkdownFileList::new(vec![
+            MarkdownFile::from_root(root, Path::new("site/nested/deeper/c.md")),
+            MarkdownFile::from_root(root, Path::new("site/b.md")),
+            MarkdownFile::from_root(root, Path::new("site/nested/b.md")),
+            MarkdownFile::from_root(root, Path::new("site/a.md")),
+            MarkdownFile::from_root(root, Path::new("site/another/a.md")),
+        ]);
+
+        // Flat list is a depth first walk of the directories
+        let flat: Vec<String> = list.get_files().iter().map(|x| x.get_link_path()).collect();
+        assert_eq!(
+            flat,
+            vec!["a", "b", "another/a", "nested/b", "nested/deeper/c"]
+        );
+
+        let top = list.get_root();
+        assert_eq!(top.get_name(), "");
+        let top_pages: Vec<String> = list.get_section_files(top)
+            .iter()
+            .map(|x| x.get_file_name())
+            .collect();
+        assert_eq!(top_pages, vec!["a", "b"]);
+
+        let names: Vec<&str> = top.get_sections().iter().map(|x| x.get_name()).collect();
+        assert_eq!(names, vec!["another", "nested"]);
+
+        let nested = &top.get_sections()[1];
+        assert_eq!(nested.get_sections()[0].get_path(), &PathBuf::from("nested/deeper"));
+        assert_eq!(list.get_section_files(&nested.get_sections()[0]).len(), 1);
+    }
+
+    #[test]
+    fn test_heading_from_front_matter() {
+        let file = MarkdownFile::from(Path::new("tests/resources/input/front-matter.md"));
+        assert_eq!(file.get_heading(), "Page With Front Matter");
+        let (front_matter, _, _) = file.read_content().unwrap();
+        assert_eq!(
+            front_matter.title(),
+            Some(&"Page With Front Matter".to_string())
+        );
+    }
+
+    #[test]
+    fn test_content_origins() {
+        let root = test_utils::temp_dir("origins-test");
+        let notes = root.join("_notes.md");
+        file_utils::write_to_file(&notes, "One\nTwo\nThree\n".to_string()).unwrap();
+        let page = root.join("page.md");
+        let source = "---\ntitle: Page\n---\n{{#include _notes.md}}\n[[Missing]]\n";
+        file_utils::write_to_file(&page, source.to_string()).unwrap();
+        let (_, content, origins) = MarkdownFile::from(&page).read_content().unwrap();
+        assert_eq!(content, "One\nTwo\nThree\n[[Missing]]\n");
+        assert_eq!(origins.len(), 4);
+        assert_eq!((&origins[0].path, origins[0].line), (&notes, 1));
+        // The link is on the fifth line of the page, after the included lines
+        assert_eq!((&origins[3].path, origins[3].line), (&page, 5));
+    }
+
     #[test]
     fn test_find_markdown_files() {
         const ROOT_DIR: &str = "tests/resources/input/site";
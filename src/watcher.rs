@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use walkdir::{DirEntry, WalkDir, WalkDirIterator};
+
+/// Watches the files within the root directory of a site for changes by
+/// periodically comparing their modification time and size. Hidden files and
+/// the output directory of the site are not watched.
+#[derive(Debug)]
+pub struct Watcher {
+    root_dir: PathBuf,
+    ignored_dir: Option<PathBuf>,
+    files: HashMap<PathBuf, (Option<SystemTime>, u64)>,
+}
+
+impl Watcher {
+    /// Start watching `root_dir`, ignoring anything within `ignored_dir`.
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(root_dir: P, ignored_dir: Q) -> Watcher {
+        let mut watcher = Watcher {
+            root_dir: root_dir.as_ref().to_path_buf(),
+            ignored_dir: ignored_dir.as_ref().canonicalize().ok(),
+            files: HashMap::new(),
+        };
+        watcher.files = watcher.snapshot();
+        watcher
+    }
+
+    /// Returns the files which have been created, modified or removed since
+    /// the last call.
+    pub fn changes(&mut self) -> Vec<PathBuf> {
+        let current = self.snapshot();
+        let mut changed: Vec<PathBuf> = current
+            .iter()
+            .filter(|&(path, state)| self.files.get(path) != Some(state))
+            .map(|(path, _)| path.clone())
+            .chain(
+                self.files
+                    .keys()
+                    .filter(|x| !current.contains_key(*x))
+                    .cloned(),
+            )
+            .collect();
+        changed.sort();
+        self.files = current;
+        changed
+    }
+
+    /// Modification time and size of every watched file
+    fn snapshot(&self) -> HashMap<PathBuf, (Option<SystemTime>, u64)> {
+        WalkDir::new(&self.root_dir)
+            .into_iter()
+            .filter_entry(|x| !self.is_ignored(x))
+            .filter_map(|x| x.ok())
+            .filter_map(|x| x.metadata().ok().map(|metadata| (x, metadata)))
+            .filter(|pair| pair.1.is_file())
+            .map(|(entry, metadata)| {
+                (
+                    entry.path().to_path_buf(),
+                    (metadata.modified().ok(), metadata.len()),
+                )
+            })
+            .collect()
+    }
+
+    fn is_ignored(&self, entry: &DirEntry) -> bool {
+        if entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.') {
+            return true;
+        }
+        match self.ignored_dir {
+            Some(ref ignored) => entry
+                .path()
+                .canonicalize()
+                .map(|x| x == *ignored)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use file_utils;
+    use test_utils;
+
+    #[test]
+    fn test_changes() {
+        let root = test_utils::temp_dir("watcher-test");
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        file_utils::write_to_file(root.join("page.md"), "# Page".to_string()).unwrap();
+
+        let mut watcher = super::Watcher::new(&root, root.join("out"));
+        assert!(watcher.changes().is_empty());
+
+        file_utils::write_to_file(root.join("page.md"), "# Changed Page".to_string()).unwrap();
+        file_utils::write_to_file(root.join("new.md"), "# New".to_string()).unwrap();
+        file_utils::write_to_file(root.join("out/page.html"), "ignored".to_string()).unwrap();
+        file_utils::write_to_file(root.join(".git/HEAD"), "ignored".to_string()).unwrap();
+        assert_eq!(watcher.changes(), vec![root.join("new.md"), root.join("page.md")]);
+
+        fs::remove_file(root.join("new.md")).unwrap();
+        assert_eq!(watcher.changes(), vec![root.join("new.md")]);
+        assert!(watcher.changes().is_empty());
+    }
+}
@@ -0,0 +1,346 @@
+use include::Origin;
+use links::PageLinks;
+
+const OPEN: &str = "[[";
+const CLOSE: &str = "]]";
+/// Markers of the fenced code blocks which wiki links are not looked for in.
+const FENCES: &[&str] = &["```", "~~~"];
+
+/// Replace the `[[Page Name]]` and `[[page-name|label]]` wiki links within
+/// the Markdown of a page with links to the pages they name, leaving those in
+/// code and HTML untouched. A warning giving the source file and line is
+/// logged when a link matches no page, in which case it is left as written, or
+/// several pages, in which case it links to the first of them. `origins`
+/// are the file and line each line of the content was written on.
+pub fn rewrite(content: &str, links: &PageLinks, origins: &[Origin]) -> String {
+    replace_links(content, |link, i| Some(wiki_link(link, links, &origins[i])))
+}
+
+/// Names of the pages the wiki links within the Markdown of a page refer to,
+/// in the order they are written.
+pub fn targets(content: &str) -> Vec<String> {
+    let mut targets = vec![];
+    replace_links(content, |link, _| {
+        targets.push(split_link(link).0.to_string());
+        None
+    });
+    targets
+}
+
+/// Replace the wiki links outside of code with the result of `replace`, given
+/// the contents of the link and the index of its line. Links are left as
+/// written when `replace` gives `None`.
+fn replace_links<F>(content: &str, mut replace: F) -> String
+where
+    F: FnMut(&str, usize) -> Option<String>,
+{
+    let mut replaced = String::with_capacity(content.len());
+    let mut blocks = Blocks::default();
+    for (i, line) in content.split_inclusive('\n').enumerate() {
+        if blocks.is_code(line) {
+            replaced.push_str(line);
+        } else {
+            replaced.push_str(&replace_in_line(line, &mut |link| replace(link, i)));
+        }
+    }
+    replaced
+}
+
+/// Follows the code blocks of the Markdown as it is read line by line. This
+/// only goes as far as telling code apart from the rest, so the lists and
+/// paragraphs which decide whether an indented line is code are approximated.
+#[derive(Debug, Default)]
+struct Blocks<'a> {
+    // Marker of the fenced code block being read
+    fence: Option<&'a str>,
+    // Whether an indented code block is being read
+    indented: bool,
+    // Whether the previous line was part of a paragraph, which an indented
+    // line continues rather than starting a code block
+    paragraph: bool,
+    // Indentation of the content of the list item being read
+    list_indent: Option<usize>,
+}
+
+impl<'a> Blocks<'a> {
+    /// Whether the line is code, the fences of a code block included
+    fn is_code(&mut self, line: &'a str) -> bool {
+        let trimmed = line.trim_start();
+        if let Some(fence) = self.fence {
+            if trimmed.starts_with(fence) {
+                self.fence = None;
+            }
+            return true;
+        }
+        if trimmed.is_empty() {
+            self.paragraph = false;
+            return self.indented;
+        }
+
+        let indent = indentation(line);
+        let content_indent = self.list_indent.unwrap_or(0);
+        if indent >= content_indent + 4 && (self.indented || !self.paragraph) {
+            self.indented = true;
+            return true;
+        }
+        self.indented = false;
+        if indent < content_indent && !self.paragraph {
+            self.list_indent = None;
+        }
+        if let Some(fence) = FENCES.iter().find(|x| trimmed.starts_with(*x)) {
+            self.fence = Some(*fence);
+            self.paragraph = false;
+            return true;
+        }
+        if let Some(width) = list_marker(trimmed) {
+            self.list_indent = Some(indent + width);
+        }
+        self.paragraph = !trimmed.starts_with('#');
+        false
+    }
+}
+
+/// Number of columns the line is indented by
+fn indentation(line: &str) -> usize {
+    let mut columns = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => columns += 1,
+            '\t' => columns += 4 - columns % 4,
+            _ => break,
+        }
+    }
+    columns
+}
+
+/// Width of the list marker starting the line, along with the spaces which
+/// follow it, or `None` when the line is not a list item.
+fn list_marker(line: &str) -> Option<usize> {
+    let digits = line.len() - line.trim_start_matches(|x: char| x.is_ascii_digit()).len();
+    let marker = match line[digits..].chars().next() {
+        Some('-') | Some('*') | Some('+') if digits == 0 => 1,
+        Some('.') | Some(')') if digits > 0 && digits < 10 => digits + 1,
+        _ => return None,
+    };
+    let rest = &line[marker..];
+    let spaces = rest.len() - rest.trim_start_matches(' ').len();
+    if rest.trim().is_empty() {
+        Some(marker + 1)
+    } else if spaces == 0 {
+        None
+    } else if spaces > 4 {
+        Some(marker + 1)
+    } else {
+        Some(marker + spaces)
+    }
+}
+
+/// Replace the wiki links within a line outside of a code block, skipping
+/// over code spans and HTML.
+fn replace_in_line<F: FnMut(&str) -> Option<String>>(line: &str, replace: &mut F) -> String {
+    let mut replaced = String::with_capacity(line.len());
+    let mut position = 0;
+    while position < line.len() {
+        let rest = &line[position..];
+        if let Some(length) = code_span(rest).or_else(|| html(rest)) {
+            replaced.push_str(&rest[..length]);
+            position += length;
+        } else if let Some(inner) = rest.strip_prefix(OPEN) {
+            let link = inner
+                .find(CLOSE)
+                .map(|x| &inner[..x])
+                .filter(|x| !x.trim().is_empty() && !x.contains('['));
+            match link {
+                Some(link) => {
+                    let written = &rest[..OPEN.len() + link.len() + CLOSE.len()];
+                    replaced.push_str(&replace(link).unwrap_or_else(|| written.to_string()));
+                    position += written.len();
+                }
+                None => {
+                    replaced.push_str(OPEN);
+                    position += OPEN.len();
+                }
+            }
+        } else {
+            let c = rest.chars().next().expect("Empty line remaining");
+            replaced.push(c);
+            position += c.len_utf8();
+        }
+    }
+    replaced
+}
+
+/// Length of the code span starting the text, which runs to the end of the
+/// line when it is not closed.
+fn code_span(text: &str) -> Option<usize> {
+    if !text.starts_with('`') {
+        return None;
+    }
+    let ticks = text.len() - text.trim_start_matches('`').len();
+    let span = text[ticks..]
+        .find(&text[..ticks])
+        .map_or(text.len(), |x| 2 * ticks + x);
+    Some(span)
+}
+
+/// Length of the HTML tag, comment or autolink starting the text
+fn html(text: &str) -> Option<usize> {
+    let inner = text.strip_prefix('<')?;
+    if inner.starts_with("!--") {
+        return text.find("-->").map(|x| x + "-->".len());
+    }
+    match inner.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '/' || c == '!' || c == '?' => {
+            text.find('>').map(|x| x + 1)
+        }
+        _ => None,
+    }
+}
+
+/// Markdown link for the contents of a wiki link, or the wiki link as it was
+/// written when it does not match a page.
+fn wiki_link(link: &str, links: &PageLinks, origin: &Origin) -> String {
+    let (target, label) = split_link(link);
+    let pages = links.wiki_pages(target);
+    let page = match pages.first() {
+        Some(page) => page,
+        None => {
+            warn!(
+                "Wiki link [[{}]] in {} does not match a page of the site",
+                link, origin
+            );
+            return format!("{}{}{}", OPEN, link, CLOSE);
+        }
+    };
+    if pages.len() > 1 {
+        warn!(
+            "Wiki link [[{}]] in {} matches the pages {}, linking to {}",
+            link,
+            origin,
+            pages.join(", "),
+            page
+        );
+    }
+    format!(
+        "[{}]({})",
+        escape_label(label),
+        links
+            .href_to(page)
+            .replace(' ', "%20")
+            .replace('(', "%28")
+            .replace(')', "%29")
+    )
+}
+
+/// Target and label of the contents of a wiki link, the label being the
+/// target when not given.
+fn split_link(link: &str) -> (&str, &str) {
+    let mut parts = link.splitn(2, '|');
+    let target = parts.next().unwrap_or("").trim();
+    let label = parts.next().map(str::trim).unwrap_or(target);
+    (target, label)
+}
+
+/// Escape the label so that it is shown as written rather than as Markdown
+fn escape_label(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        if c.is_ascii_punctuation() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use include::Origin;
+    use links::SiteLinks;
+    use walker::{MarkdownFile, MarkdownFileList};
+
+    fn site() -> SiteLinks {
+        let root = Path::new("tests/resources/input/wiki");
+        SiteLinks::new(&MarkdownFileList::new(vec![
+            MarkdownFile::from_root(root, &root.join("guide.md")),
+            MarkdownFile::from_root(root, &root.join("notes.md")),
+            MarkdownFile::from_root(root, &root.join("archive/notes.md")),
+        ]))
+    }
+
+    fn rewrite(content: &str, page: &str) -> String {
+        let site = site();
+        let origins: Vec<Origin> = (1..content.split_inclusive('\n').count() + 1)
+            .map(|line| Origin {
+                path: PathBuf::from("page.md"),
+                line,
+            })
+            .collect();
+        super::rewrite(content, &site.page(page), &origins)
+    }
+
+    #[test]
+    fn test_rewrite() {
+        assert_eq!(
+            rewrite("See [[Getting Started]] and [[archive/notes|old notes]].", "guide"),
+            "See [Getting Started](guide.html) and [old notes](archive/notes.html)."
+        );
+        assert_eq!(
+            rewrite("Back to [[guide|the ]guide]]", "archive/notes"),
+            "Back to [the \\]guide](../guide.html)"
+        );
+    }
+
+    #[test]
+    fn test_unresolved() {
+        // Ambiguous links go to the first page, missing ones are left alone
+        assert_eq!(rewrite("[[Team Notes]]", "guide"), "[Team Notes](archive/notes.html)");
+        assert_eq!(rewrite("[[Notes]]", "guide"), "[Notes](notes.html)");
+        assert_eq!(rewrite("[[Missing Page]]", "guide"), "[[Missing Page]]");
+        assert_eq!(rewrite("[[]] [[ ]]", "guide"), "[[]] [[ ]]");
+    }
+
+    #[test]
+    fn test_targets() {
+        let content = "[[Getting Started]] `[[guide]]`\n```\n[[notes]]\n```\n[[notes | Notes]]";
+        assert_eq!(super::targets(content), vec!["Getting Started", "notes"]);
+    }
+
+    #[test]
+    fn test_code_untouched() {
+        let content = "`[[guide]]` and ``a `[[guide]]` b``\n\n```\n[[guide]]\n```\n[[guide]]\n";
+        assert_eq!(
+            rewrite(content, "notes"),
+            "`[[guide]]` and ``a `[[guide]]` b``\n\n```\n[[guide]]\n```\n\
+             [guide](guide.html)\n"
+        );
+
+        // Indented code, but not a paragraph or list item continued by an
+        // indented line
+        let content = "Text\n\n    [[guide]]\n\n    [[guide]]\nText\n    [[guide]]\n";
+        assert_eq!(
+            rewrite(content, "notes"),
+            "Text\n\n    [[guide]]\n\n    [[guide]]\nText\n    [guide](guide.html)\n"
+        );
+        let content = "- Item\n\n    [[guide]]\n";
+        assert_eq!(rewrite(content, "notes"), "- Item\n\n    [guide](guide.html)\n");
+    }
+
+    #[test]
+    fn test_html_untouched() {
+        assert_eq!(
+            rewrite("<a title=\"[[guide]]\">[[guide]]</a> <!-- [[guide]] -->", "notes"),
+            "<a title=\"[[guide]]\">[guide](guide.html)</a> <!-- [[guide]] -->"
+        );
+        assert_eq!(rewrite("1 < 2 [[guide]]", "notes"), "1 < 2 [guide](guide.html)");
+    }
+
+    #[test]
+    fn test_label_escaped() {
+        assert_eq!(
+            rewrite("[[guide|*not* `code` \\ <b>]]", "notes"),
+            "[\\*not\\* \\`code\\` \\\\ \\<b\\>](guide.html)"
+        );
+    }
+}
@@ -44,6 +44,9 @@ fn test_it() {
     assert!(common::check_file_exists(
         tmp_dir.to_string_lossy().to_string() + "/second-page.html",
     ));
+    assert!(common::check_file_exists(
+        tmp_dir.to_string_lossy().to_string() + "/nested/nested-page.html",
+    ));
     assert!(common::check_file_exists(
         tmp_dir.to_string_lossy().to_string() + "/style.css",
     ));
@@ -65,12 +68,22 @@ fn test_it() {
         common::read_from_file(tmp_dir.to_string_lossy().to_string() + "/second-page.html");
     common::compare_string_content(expected, &actual.to_string());
 
+    println!("Checking nested page references resources relative to its depth");
+    let actual =
+        common::read_from_file(tmp_dir.to_string_lossy().to_string() + "/nested/nested-page.html");
+    assert!(actual.contains("href=\"../made-up.css\""));
+    assert!(actual.contains("href=\"../second_style.css\""));
+    assert!(actual.contains("href=\"../second-page.html#top\""));
+
     // Ensure the images were move across successfully
     assert!(common::check_file_exists(
         tmp_dir.to_string_lossy().to_string() + "/images/rustacean-orig-noshadow.png",
     ));
 
     // Ensure the default styles were written
+    assert!(common::check_file_exists(
+        tmp_dir.to_string_lossy().to_string() + "/bootstrap.css",
+    ));
     assert!(common::check_file_exists(
         tmp_dir.to_string_lossy().to_string() + "/highlight.css",
     ));
@@ -0,0 +1,11 @@
+use std::env;
+
+/// Print the number of arguments
+fn main() {
+    // ANCHOR: args
+    let args: Vec<String> = env::args().collect();
+    // ANCHOR_END: args
+
+    // Includes the name of the program
+    println!("{}", args.len());
+}
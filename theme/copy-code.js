@@ -0,0 +1,20 @@
+// Copies the code of a code block to the clipboard when its copy button is
+// clicked. The line numbers are drawn by the stylesheet so are not copied.
+(function () {
+    document.addEventListener("click", function (event) {
+        var button = event.target;
+        if (!button.classList || !button.classList.contains("copy-code")) {
+            return;
+        }
+        var code = button.parentNode.querySelector("code");
+        if (!code || !navigator.clipboard) {
+            return;
+        }
+        navigator.clipboard.writeText(code.textContent).then(function () {
+            button.textContent = "Copied";
+            setTimeout(function () {
+                button.textContent = "Copy";
+            }, 2000);
+        });
+    });
+})();
@@ -0,0 +1,33 @@
+// Reloads the page when made-up rebuilds the site and shows the error when
+// the build fails. Only included while serving with `--watch`.
+(function () {
+    var overlay = null;
+
+    function showError(message) {
+        if (overlay === null) {
+            overlay = document.createElement("div");
+            overlay.id = "made-up-build-error";
+            overlay.style.cssText = "position: fixed; top: 0; left: 0; right: 0; bottom: 0; " +
+                "z-index: 10000; overflow: auto; padding: 2em; " +
+                "background: rgba(0, 0, 0, 0.85); color: #ff6b6b; font-family: monospace;";
+            document.body.appendChild(overlay);
+        }
+        overlay.innerHTML = "";
+        var heading = document.createElement("h2");
+        heading.textContent = "Failed to build the site";
+        var details = document.createElement("pre");
+        details.style.cssText = "color: inherit; background: none; border: none; width: auto; " +
+            "white-space: pre-wrap;";
+        details.textContent = message;
+        overlay.appendChild(heading);
+        overlay.appendChild(details);
+    }
+
+    var events = new EventSource("/__made-up/events");
+    events.addEventListener("reload", function () {
+        window.location.reload();
+    });
+    events.addEventListener("build-error", function (event) {
+        showError(JSON.parse(event.data));
+    });
+})();